@@ -1,4 +1,3 @@
 #!/bin/bash
 
-glslc src/shaders/game.comp -o target/game.spv
-cp src/web/index.html target/index.html
\ No newline at end of file
+cp src/web/index.html target/index.html
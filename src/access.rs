@@ -0,0 +1,228 @@
+//! Who may see and change the garden. An access file, given with
+//! `access=<path>`, holds lines of `<role> <token>` granting whoever bears
+//! the token that role, and lines of `origin <origin>` letting pages from
+//! that origin call the garden with credentials. Blank lines are skipped, as
+//! are comments from a `#` starting a line or following a space, so tokens
+//! may hold `#`.
+//!
+//! Requests bear their token as `Authorization: Bearer <token>`, or as an
+//! `access_token` query parameter where headers cannot be set. With an
+//! access file, every route but the page at `/` needs one.
+//!
+//! ```text
+//! # Dashboards may watch, the greenhouse team may plant.
+//! viewer   d41d8cd98f00
+//! gardener 9e107d9d372b
+//! admin    e4d909c290d0
+//! origin   https://dashboards.example.com
+//! ```
+//!
+//! Without an access file anyone may read, plant and pin, nobody may use
+//! the admin routes, and pages from any origin may call the garden without
+//! credentials.
+
+/// What a token allows, each role everything the previous one does.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Role {
+  /// Reading the garden, traces of watched cells included.
+  Viewer,
+  /// Mutating cells, and pinning cells to trace or unpinning them.
+  Gardener,
+  /// Controlling the runner.
+  Admin,
+}
+
+impl Role {
+  pub fn parse(name: &str) -> Option<Role> {
+    match name {
+      "viewer" => Some(Role::Viewer),
+      "gardener" => Some(Role::Gardener),
+      "admin" => Some(Role::Admin),
+      _ => None,
+    }
+  }
+}
+
+pub struct Access {
+  tokens: Vec<(String, Role)>,
+  /// The role of requests without a known token, if any.
+  anonymous: Option<Role>,
+  /// Origins allowed to call with credentials. Empty for any origin,
+  /// without credentials.
+  pub origins: Vec<String>,
+}
+
+impl Access {
+  /// Access without an access file.
+  pub fn open() -> Access {
+    Access {
+      tokens: Vec::new(),
+      anonymous: Some(Role::Gardener),
+      origins: Vec::new(),
+    }
+  }
+
+  pub fn parse(source: &str) -> Result<Access, String> {
+    let mut access = Access {
+      tokens: Vec::new(),
+      anonymous: None,
+      origins: Vec::new(),
+    };
+    for (number, line) in source.lines().enumerate() {
+      let line = strip_comment(line).trim();
+      if line.is_empty() {
+        continue;
+      }
+      let mut words = line.split_whitespace();
+      let (kind, value) = match (words.next(), words.next(), words.next()) {
+        (Some(kind), Some(value), None) => (kind, value),
+        _ => {
+          return Err(format!(
+            "line {}: expected a role or origin and one value",
+            number + 1
+          ))
+        }
+      };
+      if kind == "origin" {
+        access.origins.push(value.trim_end_matches('/').to_string());
+        continue;
+      }
+      let role = Role::parse(kind).ok_or_else(|| {
+        format!(
+          "line {}: unknown role {}, expected viewer, gardener, admin or origin",
+          number + 1,
+          kind
+        )
+      })?;
+      if access.tokens.iter().any(|(token, _)| token == value) {
+        return Err(format!("line {}: the token is already granted", number + 1));
+      }
+      access.tokens.push((value.to_string(), role));
+    }
+    Ok(access)
+  }
+
+  pub fn load(path: &str) -> Result<Access, String> {
+    let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    Access::parse(&source).map_err(|e| format!("{}, {}", path, e))
+  }
+
+  /// The role of a request bearing `token`, if it has one.
+  pub fn role(&self, token: Option<&str>) -> Option<Role> {
+    token
+      .and_then(|token| {
+        self
+          .tokens
+          .iter()
+          .find(|(granted, _)| same_token(token, granted))
+          .map(|(_, role)| *role)
+      })
+      .or(self.anonymous)
+  }
+
+  /// Whether any token grants `role` or more.
+  pub fn grants(&self, role: Role) -> bool {
+    self.anonymous.is_some_and(|anonymous| anonymous >= role)
+      || self.tokens.iter().any(|(_, granted)| *granted >= role)
+  }
+
+  /// Whether pages from `origin` may call with credentials.
+  pub fn allows_origin(&self, origin: &str) -> bool {
+    self.origins.iter().any(|allowed| allowed == origin)
+  }
+}
+
+/// `line` up to the `#` starting a comment, if any.
+fn strip_comment(line: &str) -> &str {
+  let mut previous = ' ';
+  for (i, c) in line.char_indices() {
+    if c == '#' && previous.is_whitespace() {
+      return &line[..i];
+    }
+    previous = c;
+  }
+  line
+}
+
+/// Compare tokens in time independent of where they differ.
+fn same_token(given: &str, token: &str) -> bool {
+  given.len() == token.len()
+    && given
+      .bytes()
+      .zip(token.bytes())
+      .fold(0, |diff, (a, b)| diff | (a ^ b))
+      == 0
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn roles_are_ordered() {
+    assert!(Role::Viewer < Role::Gardener);
+    assert!(Role::Gardener < Role::Admin);
+  }
+
+  #[test]
+  fn parses_tokens_and_origins() {
+    let access = Access::parse(
+      "# Dashboards\nviewer v1 # read only\n\ngardener g#1\n  admin\ta1\norigin https://example.com/\n",
+    )
+    .unwrap();
+    assert!(access.role(Some("v1")) == Some(Role::Viewer));
+    assert!(access.role(Some("g#1")) == Some(Role::Gardener));
+    assert!(access.role(Some("g")).is_none());
+    assert!(access.role(Some("a1")) == Some(Role::Admin));
+    assert!(access.role(None).is_none());
+    assert!(access.allows_origin("https://example.com"));
+    assert!(!access.allows_origin("https://example.org"));
+  }
+
+  #[test]
+  fn grants_what_some_token_has() {
+    let access = Access::parse("gardener g1").unwrap();
+    assert!(access.grants(Role::Viewer));
+    assert!(access.grants(Role::Gardener));
+    assert!(!access.grants(Role::Admin));
+  }
+
+  #[test]
+  fn open_access_lets_anyone_garden() {
+    let access = Access::open();
+    assert!(access.role(None) == Some(Role::Gardener));
+    assert!(access.role(Some("anything")) == Some(Role::Gardener));
+    assert!(!access.grants(Role::Admin));
+    assert!(access.origins.is_empty());
+  }
+
+  #[test]
+  fn rejects_bad_lines() {
+    let error = |source: &str| Access::parse(source).err().unwrap();
+    assert_eq!(
+      error("viewer a\nboss b"),
+      "line 2: unknown role boss, expected viewer, gardener, admin or origin"
+    );
+    assert_eq!(
+      error("admin"),
+      "line 1: expected a role or origin and one value"
+    );
+    assert_eq!(
+      error("admin a b"),
+      "line 1: expected a role or origin and one value"
+    );
+    assert_eq!(
+      error("viewer a\nadmin a"),
+      "line 2: the token is already granted"
+    );
+  }
+
+  #[test]
+  fn compares_whole_tokens() {
+    assert!(same_token("secret", "secret"));
+    assert!(!same_token("secret", "secreT"));
+    assert!(!same_token("secret", "secrets"));
+    assert!(!same_token("", "secret"));
+    assert!(same_token("", ""));
+  }
+}
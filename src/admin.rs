@@ -0,0 +1,194 @@
+//! Control of a running garden over HTTP. The admin routes send a `Command`
+//! to the runner's thread, which carries it out between ticks and replies
+//! with the runner's status once done.
+
+use crate::args::Args;
+use crate::bench::Seeding;
+use crate::game::CellState;
+use crate::game::GameState;
+use crate::game::Runner;
+use crate::game::Terrain;
+use crate::rules::Shaders;
+use crate::seasons::set_rule;
+use crate::seasons::SeasonRules;
+use crate::terrain;
+use rocket::serde::Serialize;
+use rocket::tokio::sync::oneshot;
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+
+/// Most ticks one step runs, since the runner takes no other command meanwhile.
+pub const MAX_STEP: u32 = 10_000;
+/// Most ticks per second the runner can be asked for.
+pub const MAX_TICK_RATE: u32 = 1000;
+
+pub enum Command {
+  Status,
+  Pause,
+  Resume,
+  /// Run this many ticks now, paused or not.
+  Step(u32),
+  /// Ticks per second from now on.
+  Rate(u32),
+  /// Start over at tick 0 from a freshly seeded world.
+  Reset {
+    seeding: Seeding,
+    seed: u64,
+  },
+  /// Replace the cells, keeping the tick.
+  Load(Vec<CellState>),
+  /// Publish the current cells, even while paused.
+  Snapshot,
+  /// Set rule parameters, named as in season specs, all or none.
+  Rules(Vec<(String, i32)>),
+  /// Start a new runner for a world this many cells wide.
+  Resize(u32),
+}
+
+/// A command and where to send the outcome.
+pub struct Request {
+  pub command: Command,
+  pub reply: oneshot::Sender<Result<RunnerStatus, String>>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RunnerStatus {
+  tick: u64,
+  paused: bool,
+  tick_rate: u32,
+  world_width: u32,
+  /// Rule parameters set over the seasons'.
+  overrides: BTreeMap<String, i32>,
+  /// The runner's state, a new one after a resize.
+  #[serde(skip)]
+  pub game: Arc<GameState>,
+}
+
+/// What the runner's thread needs to carry out commands.
+pub struct Control {
+  /// Ticks per second.
+  pub tick_rate: u32,
+  args: Args,
+  shaders: Shaders,
+}
+
+impl Control {
+  pub fn new(args: Args, shaders: Shaders) -> Control {
+    Control {
+      tick_rate: args.update_rate,
+      args,
+      shaders,
+    }
+  }
+
+  /// A runner for a world `size` cells wide over `terrain`.
+  pub fn runner(&self, size: u32, terrain: &[Terrain]) -> Result<Runner, String> {
+    let args = &self.args;
+    let mut runner = Runner::new(
+      size,
+      args.topology,
+      terrain,
+      args.seasons.clone(),
+      self.shaders.clone(),
+      args.memory,
+      args.readback_rate,
+    )?;
+    runner.watch_stagnation(
+      args.cycle_window,
+      if args.reseed_after > 0 {
+        Some((args.reseed_after, args.reseed))
+      } else {
+        None
+      },
+    );
+    Ok(runner)
+  }
+
+  /// Carry out `command` on `runner`, which a resize replaces.
+  pub fn serve(&mut self, runner: &mut Runner, command: Command) -> Result<RunnerStatus, String> {
+    let world_width = runner.game_state.world_width;
+    match command {
+      Command::Status => {}
+      Command::Pause => runner.set_paused(true),
+      Command::Resume => runner.set_paused(false),
+      Command::Step(ticks) => {
+        if ticks > MAX_STEP {
+          return Err(format!("At most {} ticks can be stepped at once", MAX_STEP));
+        }
+        runner.step(ticks);
+      }
+      Command::Rate(rate) => {
+        if rate == 0 || rate > MAX_TICK_RATE {
+          return Err(format!(
+            "The tick rate must be between 1 and {}, pause instead of stopping",
+            MAX_TICK_RATE
+          ));
+        }
+        self.tick_rate = rate;
+      }
+      Command::Reset { seeding, seed } => runner.reset(&seeding.world(world_width, seed)),
+      Command::Load(cells) => {
+        if cells.len() != (world_width * world_width) as usize {
+          return Err(format!(
+            "Expected a world {} cells wide, resize first",
+            world_width
+          ));
+        }
+        runner.load(&cells);
+      }
+      Command::Snapshot => {
+        runner.snapshot();
+      }
+      Command::Rules(rules) => {
+        for (key, value) in rules.iter() {
+          set_rule(&mut SeasonRules::default(), key, *value)?;
+        }
+        for (key, value) in rules.iter() {
+          runner.override_rule(key, *value)?;
+        }
+      }
+      Command::Resize(size) => self.resize(runner, size)?,
+    }
+    Ok(self.status(runner))
+  }
+
+  /// Replace `runner` with one for a world `size` cells wide, seeded afresh
+  /// over new terrain. Queued mutations, rule overrides and pausing carry
+  /// over; watchpoints do not. `runner` is left running if the new one
+  /// cannot be built.
+  fn resize(&mut self, runner: &mut Runner, size: u32) -> Result<(), String> {
+    if self.args.show_graphics {
+      return Err("The window cannot be resized, start with show_graphics=false".to_string());
+    }
+    if size == 0 || size > u16::MAX as u32 {
+      return Err(format!("World width must be between 1 and {}", u16::MAX));
+    }
+    let terrain = terrain::generate(&self.args.terrain, size, self.args.seed)?;
+    let mut resized = self.runner(size, &terrain)?;
+    resized.mutations = runner.mutations.clone();
+    for (key, value) in runner.overrides() {
+      resized.override_rule(key, *value)?;
+    }
+    resized.set_paused(runner.paused());
+    runner.finish();
+    self.args.size = size;
+    *runner = resized;
+    // Publish the first frame, as nobody asks the new state for one yet.
+    runner.snapshot();
+    Ok(())
+  }
+
+  fn status(&self, runner: &Runner) -> RunnerStatus {
+    let game = runner.game_state.clone();
+    RunnerStatus {
+      tick: game.tick.load(Relaxed),
+      paused: runner.paused(),
+      tick_rate: self.tick_rate,
+      world_width: game.world_width,
+      overrides: runner.overrides().iter().cloned().collect(),
+      game,
+    }
+  }
+}
@@ -1,6 +1,18 @@
+use crate::bench::Seeding;
+use crate::game::CellLayout;
+use crate::game::Residency;
+use crate::game::Topology;
+use crate::rules::Counting;
+use crate::seasons::default_seasons;
+use crate::seasons::parse_seasons;
+use crate::seasons::Seasons;
+use crate::stagnation;
+use crate::stagnation::Reseed;
+use crate::terrain::parse_source;
+use crate::terrain::TerrainSource;
 use regex::Regex;
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Args {
   pub size: u32,
   pub pixel_size: u32,
@@ -8,6 +20,27 @@ pub struct Args {
   pub show_graphics: bool,
   pub draw_rate: u32,
   pub show_fps: bool,
+  pub terrain: TerrainSource,
+  pub seed: u64,
+  pub topology: Topology,
+  pub seasons: Seasons,
+  pub rules: Option<String>,
+  pub verify_rules: u32,
+  pub counting: Counting,
+  pub memory: Residency,
+  pub readback_rate: u32,
+  pub cell_layout: CellLayout,
+  pub bench: u32,
+  /// How the bench counts, one run each. Empty to count as `counting` says.
+  pub bench_counting: Vec<Counting>,
+  pub bench_world: Seeding,
+  pub bench_report: Option<String>,
+  pub scenario: Option<String>,
+  pub cycle_window: usize,
+  pub reseed_after: u64,
+  pub reseed: Reseed,
+  /// Access file granting roles to tokens, see `access.rs`.
+  pub access: Option<String>,
 }
 
 pub fn parse_args(args: Vec<String>) -> Args {
@@ -18,10 +51,33 @@ pub fn parse_args(args: Vec<String>) -> Args {
     show_graphics: false,
     draw_rate: 2,
     show_fps: true,
+    terrain: TerrainSource::Flat,
+    seed: rand::random::<u64>(),
+    topology: Topology::Moore,
+    seasons: Seasons {
+      period: 0,
+      phase: 0,
+      seasons: default_seasons(),
+    },
+    rules: None,
+    verify_rules: 0,
+    counting: Counting::Tables,
+    memory: Residency::Auto,
+    readback_rate: 30,
+    cell_layout: CellLayout::Wide,
+    bench: 0,
+    bench_counting: Vec::new(),
+    bench_world: Seeding::Sunflowers,
+    bench_report: None,
+    scenario: None,
+    cycle_window: stagnation::MAX_PERIOD,
+    reseed_after: 0,
+    reseed: Reseed::Sunflowers,
+    access: None,
   };
 
   let set = Regex::new(
-    r"(size)=(.*)|(pixel_size)=(.*)|(update_rate)=(.*)|(show_graphics)=(.*)|(draw_rate)=(.*)|(show_fps)=(.*)",
+    r"(size)=(.*)|(pixel_size)=(.*)|(update_rate)=(.*)|(show_graphics)=(.*)|(draw_rate)=(.*)|(show_fps)=(.*)|(terrain)=(.*)|(seed)=(.*)|(topology)=(.*)|(season_period)=(.*)|(season_phase)=(.*)|(seasons)=(.*)|(rules)=(.*)|(verify_rules)=(.*)|(counting)=(.*)|(memory)=(.*)|(readback_rate)=(.*)|(cell_layout)=(.*)|(bench)=(.*)|(bench_counting)=(.*)|(bench_world)=(.*)|(bench_report)=(.*)|(scenario)=(.*)|(cycle_window)=(.*)|(reseed_after)=(.*)|(reseed)=(.*)|(access)=(.*)",
   )
   .unwrap();
 
@@ -70,6 +126,101 @@ pub fn parse_args(args: Vec<String>) -> Args {
           .parse::<bool>()
           .expect(&format!("Could not parse show_fps: {}", arg_value))
       }
+      "terrain" => result.terrain = parse_source(arg_value),
+      "seed" => {
+        result.seed = arg_value
+          .parse::<u64>()
+          .unwrap_or_else(|_| panic!("Could not parse seed: {}", arg_value))
+      }
+      "topology" => {
+        result.topology = match arg_value {
+          "moore" => Topology::Moore,
+          "von_neumann" => Topology::VonNeumann,
+          "hex" => Topology::Hexagonal,
+          _ => panic!("Could not parse topology: {}", arg_value),
+        }
+      }
+      "season_period" => {
+        result.seasons.period = arg_value
+          .parse::<u64>()
+          .unwrap_or_else(|_| panic!("Could not parse season_period: {}", arg_value))
+      }
+      "season_phase" => {
+        result.seasons.phase = arg_value
+          .parse::<u64>()
+          .unwrap_or_else(|_| panic!("Could not parse season_phase: {}", arg_value))
+      }
+      "seasons" => {
+        result.seasons.seasons =
+          parse_seasons(arg_value).unwrap_or_else(|e| panic!("Could not parse seasons: {}", e))
+      }
+      "rules" => result.rules = Some(arg_value.to_string()),
+      "verify_rules" => {
+        result.verify_rules = arg_value
+          .parse::<u32>()
+          .expect(&format!("Could not parse verify_rules: {}", arg_value))
+      }
+      "counting" => {
+        result.counting = match arg_value {
+          "scan" => Counting::Scan,
+          "tables" => Counting::Tables,
+          _ => panic!("Could not parse counting: {}", arg_value),
+        }
+      }
+      "memory" => {
+        result.memory = match arg_value {
+          "auto" => Residency::Auto,
+          "device" => Residency::Device,
+          "host" => Residency::Host,
+          _ => panic!("Could not parse memory: {}", arg_value),
+        }
+      }
+      "readback_rate" => {
+        result.readback_rate = arg_value
+          .parse::<u32>()
+          .expect(&format!("Could not parse readback_rate: {}", arg_value))
+      }
+      "cell_layout" => {
+        result.cell_layout = match arg_value {
+          "wide" => CellLayout::Wide,
+          "packed" => CellLayout::Packed,
+          _ => panic!("Could not parse cell_layout: {}", arg_value),
+        }
+      }
+      "bench" => {
+        result.bench = arg_value
+          .parse::<u32>()
+          .expect(&format!("Could not parse bench: {}", arg_value))
+      }
+      "bench_counting" => {
+        result.bench_counting = match arg_value {
+          "scan" => vec![Counting::Scan],
+          "tables" => vec![Counting::Tables],
+          "both" => vec![Counting::Scan, Counting::Tables],
+          _ => panic!("Could not parse bench_counting: {}", arg_value),
+        }
+      }
+      "bench_world" => {
+        result.bench_world = Seeding::parse(arg_value)
+          .unwrap_or_else(|| panic!("Could not parse bench_world: {}", arg_value))
+      }
+      "bench_report" => result.bench_report = Some(arg_value.to_string()),
+      "scenario" => result.scenario = Some(arg_value.to_string()),
+      "cycle_window" => {
+        result.cycle_window = arg_value
+          .parse::<usize>()
+          .expect(&format!("Could not parse cycle_window: {}", arg_value))
+      }
+      "reseed_after" => {
+        result.reseed_after = arg_value
+          .parse::<u64>()
+          .expect(&format!("Could not parse reseed_after: {}", arg_value))
+      }
+      "reseed" => {
+        result.reseed = Reseed::parse(arg_value)
+          .unwrap_or_else(|| panic!("Could not parse reseed: {}", arg_value))
+      }
+      "access" => result.access = Some(arg_value.to_string()),
 
       _ => {}
     }
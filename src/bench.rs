@@ -0,0 +1,327 @@
+use crate::args::Args;
+use crate::game::CellState;
+use crate::game::Concept;
+use crate::game::Runner;
+use crate::game::Terrain;
+use crate::game::Topology;
+use crate::rules::Counting;
+use crate::rules::RuleSet;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rocket::serde::Serialize;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The world a benchmark or scenario starts from.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Seeding {
+  /// Nothing but Soil.
+  Soil,
+  /// Sunflowers on a tenth of the cells, as the garden starts.
+  Sunflowers,
+  /// See `grove`.
+  Grove,
+}
+
+impl Seeding {
+  pub fn parse(name: &str) -> Option<Seeding> {
+    match name {
+      "soil" => Some(Seeding::Soil),
+      "sunflowers" => Some(Seeding::Sunflowers),
+      "grove" => Some(Seeding::Grove),
+      _ => None,
+    }
+  }
+
+  pub fn world(self, world_width: u32, seed: u64) -> Vec<CellState> {
+    match self {
+      Seeding::Soil => vec![
+        CellState {
+          concept: Concept::Soil,
+          blood: 0,
+          joy: 0,
+        };
+        (world_width * world_width) as usize
+      ],
+      Seeding::Sunflowers => sunflowers(world_width, seed),
+      Seeding::Grove => grove(world_width, seed),
+    }
+  }
+}
+
+fn sunflowers(world_width: u32, seed: u64) -> Vec<CellState> {
+  let mut rng = StdRng::seed_from_u64(seed);
+  (0..world_width * world_width)
+    .map(|_| CellState {
+      concept: if rng.gen_range(0, 10) == 0 {
+        Concept::Sunflower
+      } else {
+        Concept::Soil
+      },
+      blood: 0,
+      joy: 0,
+    })
+    .collect()
+}
+
+/// A world thick with Dogwoods that stay deep in debt for the whole run, so
+/// every tick each of them scans its radius-15 neighbourhood. Their debt fits
+/// the packed layout too.
+fn grove(world_width: u32, seed: u64) -> Vec<CellState> {
+  let mut rng = StdRng::seed_from_u64(seed);
+  (0..world_width * world_width)
+    .map(|_| {
+      let (concept, blood) = match rng.gen_range(0, 10) {
+        0..=4 => (Concept::Dogwood, i16::MIN as i32),
+        5 => (Concept::Sunflower, 0),
+        _ => (Concept::Soil, 0),
+      };
+      CellState {
+        concept,
+        blood,
+        joy: 0,
+      }
+    })
+    .collect()
+}
+
+/// A runner loaded with `world`, past the first submission, which pays for
+/// pipeline warm-up.
+fn warm_runner(
+  args: &Args,
+  rules: &RuleSet,
+  terrain: &[Terrain],
+  counting: Counting,
+  world: &[CellState],
+) -> Runner {
+  let shaders = rules
+    .compile(args.topology, counting, args.cell_layout)
+    .unwrap_or_else(|e| panic!("Could not compile rules: {}", e));
+  let mut runner = Runner::new(
+    args.size,
+    args.topology,
+    terrain,
+    args.seasons.clone(),
+    shaders,
+    args.memory,
+    args.readback_rate,
+  )
+  .unwrap_or_else(|e| panic!("Could not start the runner: {}", e));
+  runner.load(world);
+  runner.execute();
+  runner.finish();
+  runner
+}
+
+/// Latency percentiles of one stage of a tick, in milliseconds.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Percentiles {
+  p50: f64,
+  p95: f64,
+  p99: f64,
+}
+
+impl Percentiles {
+  /// Nearest-rank percentiles of `samples`, all zero when there are none.
+  fn of(mut samples: Vec<Duration>) -> Percentiles {
+    samples.sort();
+    let at = |p: f64| {
+      let rank = (p * samples.len() as f64).ceil() as usize;
+      samples
+        .get(rank.saturating_sub(1))
+        .map_or(0.0, |d| d.as_secs_f64() * 1000.0)
+    };
+    Percentiles {
+      p50: at(0.50),
+      p95: at(0.95),
+      p99: at(0.99),
+    }
+  }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Population {
+  concept: &'static str,
+  cells: usize,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Report {
+  size: u32,
+  ticks: u32,
+  counting: &'static str,
+  layout: &'static str,
+  ticks_per_second: f64,
+  upload: Percentiles,
+  execution: Percentiles,
+  /// Only over the ticks that were read back, see `readbacks`.
+  readback: Percentiles,
+  readbacks: usize,
+  population: Vec<Population>,
+}
+
+impl Report {
+  fn stages(&self) -> [(&'static str, &Percentiles); 3] {
+    [
+      ("upload", &self.upload),
+      ("execution", &self.execution),
+      ("readback", &self.readback),
+    ]
+  }
+
+  /// The CSV header line and this report's row.
+  fn csv(&self) -> (String, String) {
+    let mut header: Vec<String> = ["size", "ticks", "counting", "layout", "ticks_per_second"]
+      .iter()
+      .map(|column| column.to_string())
+      .collect();
+    let mut row = vec![
+      self.size.to_string(),
+      self.ticks.to_string(),
+      self.counting.to_string(),
+      self.layout.to_string(),
+      format!("{:.3}", self.ticks_per_second),
+    ];
+    for (stage, percentiles) in self.stages().iter() {
+      for (name, value) in [
+        ("p50", percentiles.p50),
+        ("p95", percentiles.p95),
+        ("p99", percentiles.p99),
+      ]
+      .iter()
+      {
+        header.push(format!("{}_{}_ms", stage, name));
+        row.push(format!("{:.4}", value));
+      }
+    }
+    header.push("readbacks".to_string());
+    row.push(self.readbacks.to_string());
+    for population in self.population.iter() {
+      header.push(population.concept.to_string());
+      row.push(population.cells.to_string());
+    }
+    (header.join(","), row.join(","))
+  }
+}
+
+/// Run `args.bench` ticks headless as fast as possible, once per counting of
+/// `args.bench_counting`, and report the rate, per-stage latencies and the
+/// final population of each, e.g.
+/// `size=1024 bench=500 bench_world=grove bench_counting=both bench_report=grove.csv`.
+/// The report is written as JSON unless its path ends in `.csv`: one object,
+/// or a list of them when comparing countings.
+pub fn report(args: &Args, rules: &RuleSet, terrain: &[Terrain]) {
+  let countings = if args.bench_counting.is_empty() {
+    vec![args.counting]
+  } else {
+    args.bench_counting.clone()
+  };
+  if args.topology != Topology::Moore && countings.contains(&Counting::Tables) {
+    println!("Only Moore worlds count with tables; tables runs will scan.");
+  }
+
+  let reports: Vec<Report> = countings
+    .iter()
+    .map(|counting| run(args, rules, terrain, *counting))
+    .collect();
+  if let [scan, tables] = &reports[..] {
+    println!(
+      "tables are {:.1}x faster",
+      tables.ticks_per_second / scan.ticks_per_second
+    );
+  }
+
+  if let Some(path) = &args.bench_report {
+    let contents = if path.ends_with(".csv") {
+      let rows: Vec<(String, String)> = reports.iter().map(Report::csv).collect();
+      let mut csv = format!("{}\n", rows[0].0);
+      for (_, row) in rows.iter() {
+        csv.push_str(row);
+        csv.push('\n');
+      }
+      csv
+    } else if let [report] = &reports[..] {
+      rocket::serde::json::to_pretty_string(report).expect("failed to serialize report")
+    } else {
+      rocket::serde::json::to_pretty_string(&reports).expect("failed to serialize report")
+    };
+    std::fs::write(path, contents).expect("failed to write report");
+    println!("report written to {}", path);
+  }
+}
+
+/// One run of the bench counting with `counting`, printed as it finishes.
+fn run(args: &Args, rules: &RuleSet, terrain: &[Terrain], counting: Counting) -> Report {
+  let world = args.bench_world.world(args.size, args.seed);
+  let mut runner = warm_runner(args, rules, terrain, counting, &world);
+  runner.record_timings();
+
+  let start = Instant::now();
+  for _ in 0..args.bench {
+    // As if someone were always watching, so frames are read back at the
+    // readback rate.
+    runner.game_state.request_frame();
+    runner.execute();
+  }
+  runner.finish();
+  let elapsed = start.elapsed();
+  let timings = runner.take_timings();
+
+  let cells = runner.snapshot();
+  let population = Concept::ALL
+    .iter()
+    .map(|concept| Population {
+      concept: concept.name(),
+      cells: cells.iter().filter(|cell| cell.concept == *concept).count(),
+    })
+    .collect();
+
+  // Ticks that were not read back spent no time downloading.
+  let downloads: Vec<Duration> = timings
+    .iter()
+    .map(|timing| timing.download)
+    .filter(|download| *download > Duration::default())
+    .collect();
+  let report = Report {
+    size: args.size,
+    ticks: args.bench,
+    counting: match counting {
+      Counting::Scan => "scan",
+      Counting::Tables => "tables",
+    },
+    layout: args.cell_layout.name(),
+    ticks_per_second: args.bench as f64 / elapsed.as_secs_f64(),
+    upload: Percentiles::of(timings.iter().map(|timing| timing.upload).collect()),
+    execution: Percentiles::of(timings.iter().map(|timing| timing.execution).collect()),
+    readbacks: downloads.len(),
+    readback: Percentiles::of(downloads),
+    population,
+  };
+
+  println!(
+    "{} ticks over {}x{} counting with {} in {:.2}s: {:.2} ticks/s",
+    report.ticks,
+    report.size,
+    report.size,
+    report.counting,
+    elapsed.as_secs_f64(),
+    report.ticks_per_second
+  );
+  for (stage, percentiles) in report.stages().iter() {
+    println!(
+      "{:>9}: p50 {:.3}ms  p95 {:.3}ms  p99 {:.3}ms",
+      stage, percentiles.p50, percentiles.p95, percentiles.p99
+    );
+  }
+  println!(
+    "{} of {} ticks were read back",
+    report.readbacks, report.ticks
+  );
+  for population in report.population.iter() {
+    println!("{:>9}: {}", population.concept, population.cells);
+  }
+  report
+}
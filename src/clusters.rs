@@ -0,0 +1,109 @@
+//! Connected clusters of cells sharing a concept: Sunflower colonies, Rose
+//! beds, Dogwood groves. Cells are connected when they are neighbours at
+//! radius 1 under the world's topology. Soil is never clustered.
+
+use crate::game::CellState;
+use crate::game::Concept;
+use crate::game::Topology;
+use rocket::serde::Serialize;
+
+#[derive(Serialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct Cluster {
+  pub concept: &'static str,
+  pub size: usize,
+  /// Bounding box, inclusive.
+  pub min_x: u32,
+  pub min_y: u32,
+  pub max_x: u32,
+  pub max_y: u32,
+  /// Mean position of the cells, in cells.
+  pub centroid: (f64, f64),
+}
+
+fn root(parents: &mut [usize], mut idx: usize) -> usize {
+  while parents[idx] != idx {
+    parents[idx] = parents[parents[idx]];
+    idx = parents[idx];
+  }
+  idx
+}
+
+fn union(parents: &mut [usize], a: usize, b: usize) {
+  let (a, b) = (root(parents, a), root(parents, b));
+  if a != b {
+    parents[a.max(b)] = a.min(b);
+  }
+}
+
+/// Neighbours of (x, y) already visited in row-major order: the one before it
+/// in its row and those in the row above.
+fn earlier_neighbours(topology: Topology, x: i64, y: i64) -> Vec<(i64, i64)> {
+  match topology {
+    Topology::Moore => vec![(x - 1, y), (x - 1, y - 1), (x, y - 1), (x + 1, y - 1)],
+    Topology::VonNeumann => vec![(x - 1, y), (x, y - 1)],
+    // Odd rows sit half a cell to the right of even ones.
+    Topology::Hexagonal if y & 1 == 1 => vec![(x - 1, y), (x, y - 1), (x + 1, y - 1)],
+    Topology::Hexagonal => vec![(x - 1, y), (x - 1, y - 1), (x, y - 1)],
+  }
+}
+
+/// Every cluster of the world, largest first.
+pub fn label(cells: &[CellState], world_width: u32, topology: Topology) -> Vec<Cluster> {
+  let width = world_width as i64;
+  let mut parents: Vec<usize> = (0..cells.len()).collect();
+  for (idx, cell) in cells.iter().enumerate() {
+    if cell.concept == Concept::Soil {
+      continue;
+    }
+    let (x, y) = (idx as i64 % width, idx as i64 / width);
+    for (nx, ny) in earlier_neighbours(topology, x, y) {
+      if nx < 0 || ny < 0 || nx >= width {
+        continue;
+      }
+      let neighbour = (nx + ny * width) as usize;
+      if cells[neighbour].concept == cell.concept {
+        union(&mut parents, idx, neighbour);
+      }
+    }
+  }
+
+  let mut slots: Vec<Option<usize>> = vec![None; cells.len()];
+  let mut clusters: Vec<Cluster> = Vec::new();
+  let mut sums: Vec<(u64, u64)> = Vec::new();
+  for (idx, cell) in cells.iter().enumerate() {
+    if cell.concept == Concept::Soil {
+      continue;
+    }
+    let (x, y) = ((idx as i64 % width) as u32, (idx as i64 / width) as u32);
+    let cluster_root = root(&mut parents, idx);
+    let slot = *slots[cluster_root].get_or_insert_with(|| {
+      clusters.push(Cluster {
+        concept: cell.concept.name(),
+        size: 0,
+        min_x: x,
+        min_y: y,
+        max_x: x,
+        max_y: y,
+        centroid: (0.0, 0.0),
+      });
+      sums.push((0, 0));
+      clusters.len() - 1
+    });
+    let cluster = &mut clusters[slot];
+    cluster.size += 1;
+    cluster.min_x = cluster.min_x.min(x);
+    cluster.min_y = cluster.min_y.min(y);
+    cluster.max_x = cluster.max_x.max(x);
+    cluster.max_y = cluster.max_y.max(y);
+    sums[slot].0 += x as u64;
+    sums[slot].1 += y as u64;
+  }
+
+  for (cluster, (sum_x, sum_y)) in clusters.iter_mut().zip(sums) {
+    let size = cluster.size as f64;
+    cluster.centroid = (sum_x as f64 / size, sum_y as f64 / size);
+  }
+  clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.size));
+  clusters
+}
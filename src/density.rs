@@ -0,0 +1,84 @@
+//! Coarse summaries of the garden for `/garden/density`: for each block of
+//! cells, how many there are of each concept and their mean blood and joy.
+//! `density.comp` reduces a tick on the GPU when one is asked for, so only
+//! the summaries are read back. Each of its invocations reads a whole block,
+//! so blocks are at most `MAX_BLOCK` cells a side.
+//!
+//! The binary form is the blocks row by row, each the counts of every
+//! concept as `u32`, in the order of `Concept::ALL`, then the mean blood
+//! and joy as `f32`, all little-endian.
+
+use crate::game::Concept;
+use rocket::serde::Serialize;
+
+/// Words `density.comp` writes per block.
+pub const DENSITY_WORDS: usize = 8;
+/// Smallest block summarized, which bounds the blocks read back.
+pub const MIN_BLOCK: u32 = 16;
+/// Largest block summarized, which bounds the cells an invocation reads.
+pub const MAX_BLOCK: u32 = 256;
+
+/// Blocks a side of a world `world_width` cells wide.
+pub fn blocks_wide(world_width: u32, block: u32) -> u32 {
+  world_width.div_ceil(block)
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Summary {
+  /// Cells of each concept, in the order of `Concept::ALL`.
+  pub counts: [u32; Concept::ALL.len()],
+  pub blood: f32,
+  pub joy: f32,
+}
+
+/// The summaries of one tick.
+pub struct Density {
+  /// Ticks run before the summarized one.
+  pub tick: u64,
+  /// Cells a side of each block.
+  pub block: u32,
+  /// Blocks a side.
+  pub width: u32,
+  /// Row by row.
+  pub blocks: Vec<Summary>,
+}
+
+impl Density {
+  /// Summaries as `density.comp` wrote them.
+  pub fn read(tick: u64, block: u32, world_width: u32, words: &[u32]) -> Density {
+    let width = blocks_wide(world_width, block);
+    let blocks = words
+      .chunks_exact(DENSITY_WORDS)
+      .take((width * width) as usize)
+      .map(|words| {
+        let mut counts = [0; Concept::ALL.len()];
+        counts.copy_from_slice(&words[..Concept::ALL.len()]);
+        Summary {
+          counts,
+          blood: f32::from_bits(words[6]),
+          joy: f32::from_bits(words[7]),
+        }
+      })
+      .collect();
+    Density {
+      tick,
+      block,
+      width,
+      blocks,
+    }
+  }
+
+  /// The binary form described above.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut out = Vec::with_capacity(self.blocks.len() * DENSITY_WORDS * 4);
+    for summary in &self.blocks {
+      for count in summary.counts {
+        out.extend_from_slice(&count.to_le_bytes());
+      }
+      out.extend_from_slice(&summary.blood.to_le_bytes());
+      out.extend_from_slice(&summary.joy.to_le_bytes());
+    }
+    out
+  }
+}
@@ -1,14 +1,50 @@
+use crate::clusters;
+use crate::clusters::Cluster;
+use crate::density;
+use crate::density::Density;
+use crate::density::DENSITY_WORDS;
+use crate::rules::RuleSet;
+use crate::rules::Shaders;
+use crate::rules::World;
+use crate::seasons::set_rule;
+use crate::seasons::Season;
+use crate::seasons::SeasonRules;
+use crate::seasons::Seasons;
+use crate::stagnation;
+use crate::stagnation::Census;
+use crate::stagnation::Change;
+use crate::stagnation::Reseed;
+use crate::stagnation::Stagnation;
+use crate::stagnation::Watch;
+use crate::tiles::Level;
+use crate::trace::Step;
+use crate::trace::Trace;
+use crate::trace::Watchpoint;
+use crate::trace::MAX_WATCHPOINTS;
+use crate::trace::TRACE_WORDS;
 use ash::version::DeviceV1_0;
 use ash::vk;
+use rocket::serde::de::Error;
+use rocket::serde::{Deserialize, Deserializer, Serialize, Serializer};
+use rocket::tokio::sync::broadcast;
+use rocket::tokio::sync::futures::Notified;
+use rocket::tokio::sync::Notify;
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::ffi::CString;
-use std::path::PathBuf;
 use std::rc::Rc;
-use std::sync::atomic::AtomicI32;
-use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering::Relaxed;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::RwLock;
+use std::sync::Weak;
+use std::time::Duration;
+use std::time::Instant;
+use wyzoid::high::job::JobTimings;
 use wyzoid::high::job::JobTimingsBuilder;
 use wyzoid::low::vkcmd;
 use wyzoid::low::vkdescriptor;
@@ -16,6 +52,7 @@ use wyzoid::low::vkfence;
 use wyzoid::low::vkmem;
 use wyzoid::low::vkpipeline;
 use wyzoid::low::vkshader;
+use wyzoid::low::vkstate;
 use wyzoid::low::vkstate::VulkanState;
 
 #[derive(PartialEq, Clone, Copy)]
@@ -29,7 +66,52 @@ pub enum Concept {
     Thistle = 5,
 }
 
-#[derive(Clone, Copy)]
+impl Concept {
+    /// Every concept, indexed by its value.
+    pub const ALL: [Concept; 6] = [
+        Concept::Soil,
+        Concept::Sunflower,
+        Concept::Rose,
+        Concept::Dogwood,
+        Concept::Elder,
+        Concept::Thistle,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Concept::Soil => "Soil",
+            Concept::Sunflower => "Sunflower",
+            Concept::Rose => "Rose",
+            Concept::Dogwood => "Dogwood",
+            Concept::Elder => "Elder",
+            Concept::Thistle => "Thistle",
+        }
+    }
+
+    pub fn named(name: &str) -> Option<Concept> {
+        Concept::ALL
+            .iter()
+            .copied()
+            .find(|concept| concept.name() == name)
+    }
+}
+
+/// Concepts are serialized by name.
+impl Serialize for Concept {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for Concept {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Concept, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Concept::named(&name).ok_or_else(|| D::Error::custom(format!("unknown concept {}", name)))
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
 #[repr(C)]
 pub struct CellState {
     pub concept: Concept,
@@ -37,52 +119,604 @@ pub struct CellState {
     pub joy: i32,
 }
 
+/// How cells are stored on the GPU, in frames and in `/garden` responses.
+/// Values are little-endian.
+#[derive(PartialEq, Clone, Copy)]
+pub enum CellLayout {
+    /// 12 bytes: the concept, blood and joy as `i32`s, like `CellState`.
+    Wide,
+    /// 4 bytes: a `u8` concept, an `i16` blood and a `u8` joy. Storing a cell
+    /// saturates blood to the `i16` range and joy to 0..=255.
+    Packed,
+}
+
+impl CellLayout {
+    /// Bytes per cell.
+    pub fn cell_size(self) -> usize {
+        match self {
+            CellLayout::Wide => std::mem::size_of::<CellState>(),
+            CellLayout::Packed => 4,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CellLayout::Wide => "wide",
+            CellLayout::Packed => "packed",
+        }
+    }
+
+    /// Write `cell` into the first `cell_size` bytes of `out`.
+    pub fn encode(self, cell: &CellState, out: &mut [u8]) {
+        match self {
+            CellLayout::Wide => {
+                out[0..4].copy_from_slice(&(cell.concept as i32).to_le_bytes());
+                out[4..8].copy_from_slice(&cell.blood.to_le_bytes());
+                out[8..12].copy_from_slice(&cell.joy.to_le_bytes());
+            }
+            CellLayout::Packed => {
+                let blood = cell.blood.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+                out[0] = cell.concept as u8;
+                out[1..3].copy_from_slice(&blood.to_le_bytes());
+                out[3] = cell.joy.clamp(0, u8::MAX as i32) as u8;
+            }
+        }
+    }
+
+    /// Read the cell stored in the first `cell_size` bytes of `bytes`.
+    pub fn decode(self, bytes: &[u8]) -> CellState {
+        let int = |at: usize| {
+            i32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+        match self {
+            CellLayout::Wide => CellState {
+                concept: Concept::ALL[int(0) as usize],
+                blood: int(4),
+                joy: int(8),
+            },
+            CellLayout::Packed => CellState {
+                concept: Concept::ALL[bytes[0] as usize],
+                blood: i16::from_le_bytes([bytes[1], bytes[2]]) as i32,
+                joy: bytes[3] as i32,
+            },
+        }
+    }
+
+    /// `cell` as it reads back once stored.
+    pub fn round(self, cell: CellState) -> CellState {
+        let mut bytes = [0; std::mem::size_of::<CellState>()];
+        self.encode(&cell, &mut bytes);
+        self.decode(&bytes)
+    }
+
+    pub fn encode_all(self, cells: &[CellState]) -> Vec<u8> {
+        let mut bytes = vec![0; cells.len() * self.cell_size()];
+        for (cell, out) in cells.iter().zip(bytes.chunks_exact_mut(self.cell_size())) {
+            self.encode(cell, out);
+        }
+        bytes
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+#[repr(C)]
+pub enum Terrain {
+    Plain = 0,
+    Meadow = 1,
+    Rock = 2,
+    Water = 3,
+}
+
+/// Shape of the neighbourhood every rule scans, shared by the whole world.
+/// Hexagonal worlds offset every odd row by half a cell.
+#[derive(PartialEq, Clone, Copy)]
+#[repr(C)]
+pub enum Topology {
+    Moore = 0,
+    VonNeumann = 1,
+    Hexagonal = 2,
+}
+
 impl Into<u8> for CellState {
     fn into(self) -> u8 {
         self.concept as u8
     }
 }
 
+/// A cell replaced before a tick runs, instead of running its rules.
+/// Mutations of one cell in the same tick apply in order, each replacing
+/// the fields it gives.
 #[derive(Clone, Copy)]
-#[repr(C)]
 pub struct Mutation {
     pub x: u32,
     pub y: u32,
     pub concept: Concept,
+    /// The cell's own blood and joy are kept unless given.
+    pub blood: Option<i32>,
+    pub joy: Option<i32>,
+}
+
+impl Mutation {
+    /// Plant `concept` at (x, y), keeping the cell's blood and joy.
+    pub fn plant(x: u32, y: u32, concept: Concept) -> Mutation {
+        Mutation {
+            x,
+            y,
+            concept,
+            blood: None,
+            joy: None,
+        }
+    }
+
+    /// What the mutation leaves of `cell`.
+    pub fn apply(&self, cell: &CellState) -> CellState {
+        CellState {
+            concept: self.concept,
+            blood: self.blood.unwrap_or(cell.blood),
+            joy: self.joy.unwrap_or(cell.joy),
+        }
+    }
+}
+
+/// Most mutations one tick applies, and so the largest batch.
+pub const MAX_MUTATIONS: usize = 100;
+
+/// Mutations waiting for a tick, by batch. Batches apply in the order they
+/// were queued, each within a single tick, so none may hold more than
+/// `MAX_MUTATIONS`.
+pub type Mutations = Arc<Mutex<VecDeque<Vec<Mutation>>>>;
+
+/// Take the batches the next tick applies: as many whole ones from the
+/// front of `queue` as fit.
+fn next_mutations(queue: &mut VecDeque<Vec<Mutation>>) -> Vec<Mutation> {
+    let mut mutations = Vec::new();
+    while let Some(batch) = queue.front() {
+        if mutations.len() + batch.len() > MAX_MUTATIONS {
+            break;
+        }
+        mutations.extend(queue.pop_front().unwrap());
+    }
+    mutations
+}
+
+/// A `Mutation` as the rule pass reads it.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct ShaderMutation {
+    x: u32,
+    y: u32,
+    concept: Concept,
+    blood: i32,
+    joy: i32,
+    /// Bit 0 set to replace blood, bit 1 to replace joy.
+    fields: u32,
+}
+
+impl From<Mutation> for ShaderMutation {
+    fn from(mutation: Mutation) -> ShaderMutation {
+        ShaderMutation {
+            x: mutation.x,
+            y: mutation.y,
+            concept: mutation.concept,
+            blood: mutation.blood.unwrap_or(0),
+            joy: mutation.joy.unwrap_or(0),
+            fields: mutation.blood.is_some() as u32 | (mutation.joy.is_some() as u32) << 1,
+        }
+    }
+}
+
+/// Side of the square workgroup declared in `rules.comp`.
+const WORKGROUP_SIZE: u32 = 8;
+/// Lines of the world each workgroup of `tables.comp` sums.
+const TABLE_GROUP_SIZE: u32 = 64;
+/// Rows of the world each workgroup of `census.comp` hashes.
+const CENSUS_GROUP_SIZE: u32 = 64;
+/// Blocks each workgroup of `density.comp` summarizes.
+const DENSITY_GROUP_SIZE: u32 = 64;
+/// Ticks submitted to the GPU before the runner waits for the oldest.
+const FRAMES_IN_FLIGHT: usize = 2;
+/// Stagnation changes kept for followers that have yet to read them.
+const STAGNATION_CHANGES: usize = 16;
+
+/// Workgroups needed along each axis to cover the world, checked against the
+/// device limits. The shader skips invocations that fall outside the world.
+fn dispatch_size(
+    limits: &vk::PhysicalDeviceLimits,
+    world_width: u32,
+    buffer_size: u64,
+) -> Result<u32, String> {
+    let groups = world_width.div_ceil(WORKGROUP_SIZE);
+    let max_groups = std::cmp::min(
+        limits.max_compute_work_group_count[0],
+        limits.max_compute_work_group_count[1],
+    );
+    if groups > max_groups {
+        return Err(format!(
+            "A world {} cells wide needs {} workgroups per axis, the device allows {}",
+            world_width, groups, max_groups
+        ));
+    }
+    if WORKGROUP_SIZE * WORKGROUP_SIZE > limits.max_compute_work_group_invocations {
+        return Err(format!(
+            "The device only allows {} invocations per workgroup",
+            limits.max_compute_work_group_invocations
+        ));
+    }
+    if buffer_size > limits.max_storage_buffer_range as u64 {
+        return Err(format!(
+            "A world {} cells wide needs {} byte buffers, the device allows {}. Counting with tables needs the most",
+            world_width, buffer_size, limits.max_storage_buffer_range
+        ));
+    }
+    Ok(groups)
 }
 
 #[repr(C)]
 struct ShaderParams {
     world_width: u32,
     flip: i32,
+    topology: Topology,
+    tick: u32,
+    season: SeasonRules,
     mutations_size: u32,
-    mutations: [Mutation; 100],
+    mutations: [ShaderMutation; MAX_MUTATIONS],
+    watches_size: u32,
+    watches: [u32; MAX_WATCHPOINTS],
+    density_block: u32,
+}
+
+/// Where the cell, terrain and table buffers live.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Residency {
+    /// Device-local on discrete GPUs, host-visible everywhere else.
+    Auto,
+    /// Device-local, copied to a host-visible staging buffer when a frame is wanted.
+    Device,
+    /// Host-visible and coherent, read in place.
+    Host,
 }
 
 pub struct Runner {
     vulkan: Rc<VulkanState>,
+    cells: [vkmem::VkBuffer; 2],
+    staging: Option<vkmem::VkBuffer>,
+    _buffers: Vec<vkmem::VkBuffer>,
     _memory: vkmem::VkMem,
+    _host_memory: vkmem::VkMem,
     world_width: u32,
-    timing: JobTimingsBuilder,
+    topology: Topology,
+    layout: CellLayout,
     fence: vkfence::VkFence,
     cmd_pool: vkcmd::VkCmdPool,
+    /// Recorded again by each `copy_now`.
+    copy_cmd: usize,
+    /// Per frame in flight: the tick's command buffer, its fence and its params.
+    tick_cmds: Vec<usize>,
+    fences: Vec<vkfence::VkFence>,
+    param_data: Vec<*mut ShaderParams>,
+    /// Per frame in flight: the census rows of its tick, then the traces of
+    /// its watched cells, then its density summaries if asked for.
+    census_data: Vec<*const u32>,
+    in_flight: Vec<Option<InFlight>>,
+    next_frame: usize,
+    /// Ticks handed to the GPU, finished or not.
+    submitted: u64,
+    last_timing: JobTimingsBuilder,
+    /// Timings of every finished tick not taken yet, once recording.
+    timing_log: Option<Vec<JobTimings>>,
+    /// Copies of the left or right buffer into the staging buffer.
+    readback_cmds: Vec<usize>,
     flip: i32,
-    left_data: *mut CellState,
-    right_data: *mut CellState,
-    param_data: *mut ShaderParams,
+    /// Where the host reads the left or right buffer: the buffers themselves,
+    /// or the staging buffer they are copied to when device-local.
+    readable: [*mut u8; 2],
+    /// The frame replaced by the last publication, reused once no reader holds it.
+    spare: Option<Arc<Frame>>,
+    publications: u64,
+    readback_interval: Duration,
+    next_readback: Instant,
     paused: bool,
+    /// Rule settings that win over the seasons', by season spec key.
+    overrides: Vec<(String, i32)>,
+    watch: Watch,
+    /// Stagnant ticks to wait for before reseeding, and how.
+    reseed: Option<(u64, Reseed)>,
 
-    pub mutations: Arc<Mutex<Vec<Mutation>>>,
+    pub mutations: Mutations,
     pub game_state: Arc<GameState>,
 }
 
+/// A tick submitted to the GPU that the runner has not waited on yet.
+struct InFlight {
+    /// The buffer the tick writes, 0 for left and 1 for right.
+    written: usize,
+    /// Whether to publish the tick once it is done. Its cells were also
+    /// copied to staging if need be.
+    publish: bool,
+    timing: JobTimingsBuilder,
+    /// The cells the tick traces, in the order of their traces.
+    watched: Vec<(u32, u32)>,
+    /// Cells a side of the blocks the tick summarizes, 0 for none.
+    density_block: u32,
+}
+
+/// A complete tick, copied out of GPU memory. Never changes once published.
+pub struct Frame {
+    /// Ticks run before this frame.
+    pub tick: u64,
+    /// Frames published before this one. Loading cells publishes a frame
+    /// without running a tick, so only this tells such frames apart.
+    pub publication: u64,
+    pub layout: CellLayout,
+    bytes: Vec<u8>,
+}
+
+impl Frame {
+    /// The cells exactly as laid out in GPU memory.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// A frame of `cells`, as if the runner had published it.
+    #[cfg(test)]
+    pub fn of(tick: u64, layout: CellLayout, cells: &[CellState]) -> Frame {
+        Frame {
+            tick,
+            publication: 0,
+            layout,
+            bytes: layout.encode_all(cells),
+        }
+    }
+
+    /// Validator of the frame's cells, for `ETag` headers.
+    pub fn etag(&self) -> String {
+        format!("\"{}-{}\"", self.tick, self.publication)
+    }
+
+    /// The cell at index `idx`, if the frame has one.
+    pub fn cell(&self, idx: usize) -> Option<CellState> {
+        let size = self.layout.cell_size();
+        self.bytes
+            .get(idx * size..(idx + 1) * size)
+            .map(|bytes| self.layout.decode(bytes))
+    }
+
+    /// The `w` by `h` cells from (`x`, `y`) of a world `world_width` cells
+    /// wide, row by row. The region must lie within the world.
+    pub fn region(&self, world_width: u32, x: u32, y: u32, w: u32, h: u32) -> Vec<CellState> {
+        (y..y + h)
+            .flat_map(|row| (x..x + w).map(move |column| (column + row * world_width) as usize))
+            .map(|idx| self.cell(idx).expect("regions lie within the world"))
+            .collect()
+    }
+
+    /// Every cell in order, decoded.
+    pub fn cells(&self) -> impl ExactSizeIterator<Item = CellState> + '_ {
+        self.bytes
+            .chunks_exact(self.layout.cell_size())
+            .map(move |bytes| self.layout.decode(bytes))
+    }
+}
+
+/// Tile levels by block size.
+type Levels = HashMap<u32, Arc<Level>>;
+
 pub struct GameState {
-    pub game_data: AtomicPtr<CellState>,
-    pub game_size: AtomicI32,
+    frame: RwLock<Arc<Frame>>,
+    pub tick: AtomicU64,
+    pub world_width: u32,
+    pub topology: Topology,
+    pub terrain: Arc<Vec<Terrain>>,
+    pub seasons: Seasons,
+    frame_wanted: AtomicBool,
+    published: Notify,
+    stagnation: RwLock<Option<(Stagnation, u64)>>,
+    /// Each change of `stagnation`, as it is noticed.
+    stagnation_changes: broadcast::Sender<Change>,
+    /// The clusters of a frame, kept until another frame's are wanted.
+    clusters: Mutex<(Weak<Frame>, Arc<Vec<Cluster>>)>,
+    /// Tile levels of a frame by block size, dropped once a newer frame's
+    /// are wanted.
+    levels: Mutex<(Weak<Frame>, Levels)>,
+    /// Labels of the rule branches traces report.
+    pub branches: Vec<String>,
+    watchpoints: Mutex<Vec<Watchpoint>>,
+    /// Block size of the density summaries wanted from the next tick, 0 for
+    /// none.
+    density_wanted: AtomicU32,
+    density: RwLock<Option<Arc<Density>>>,
+    densities: Notify,
+}
+
+/// The game state being served. Resizing the world starts a runner with a
+/// state of its own, which then replaces the old one here.
+pub struct Garden(RwLock<Arc<GameState>>);
+
+impl Garden {
+    pub fn new(state: Arc<GameState>) -> Garden {
+        Garden(RwLock::new(state))
+    }
+
+    pub fn current(&self) -> Arc<GameState> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Swap in `state`, waking those waiting on the old world's frames so
+    /// they can move on to the new one.
+    pub fn replace(&self, state: Arc<GameState>) {
+        let old = std::mem::replace(&mut *self.0.write().unwrap(), state);
+        old.published.notify_waiters();
+    }
+}
+
+impl GameState {
+    pub fn season(&self) -> Option<&Season> {
+        self.seasons.at(self.tick.load(Relaxed))
+    }
+
+    /// The most recently published frame.
+    pub fn frame(&self) -> Arc<Frame> {
+        self.frame.read().unwrap().clone()
+    }
+
+    /// Ask for a fresh frame to be published. Cells are only copied out of
+    /// GPU memory after someone asks, at most at the readback rate.
+    pub fn request_frame(&self) {
+        self.frame_wanted.store(true, Relaxed);
+    }
+
+    /// Completes once the frame after the current one is published. Take it
+    /// before reading the current frame so that none is missed.
+    pub fn next_frame(&self) -> Notified<'_> {
+        self.published.notified()
+    }
+
+    /// The latest frame with its clusters, largest first. They are labelled
+    /// on the CPU the first time each frame's are asked for.
+    pub fn clusters(&self) -> (Arc<Frame>, Arc<Vec<Cluster>>) {
+        let frame = self.frame();
+        let mut cached = self.clusters.lock().unwrap();
+        if !Weak::ptr_eq(&cached.0, &Arc::downgrade(&frame)) {
+            let cells: Vec<CellState> = frame.cells().collect();
+            *cached = (
+                Arc::downgrade(&frame),
+                Arc::new(clusters::label(&cells, self.world_width, self.topology)),
+            );
+        }
+        (frame, cached.1.clone())
+    }
+
+    /// The latest frame downsampled to `block` by `block` cells, see
+    /// `tiles::Level`. Each level is built the first time it is asked for
+    /// after a frame is published.
+    pub fn level(&self, block: u32) -> (Arc<Frame>, Arc<Level>) {
+        let frame = self.frame();
+        let mut cached = self.levels.lock().unwrap();
+        if !Weak::ptr_eq(&cached.0, &Arc::downgrade(&frame)) {
+            *cached = (Arc::downgrade(&frame), HashMap::new());
+        }
+        let level = cached
+            .1
+            .entry(block)
+            .or_insert_with(|| Arc::new(Level::downsample(&frame, self.world_width, block)))
+            .clone();
+        (frame, level)
+    }
+
+    /// Ask for the next tick to be summarized in blocks of `block` cells a
+    /// side, replacing any other block size asked for meanwhile.
+    pub fn request_density(&self, block: u32) {
+        self.density_wanted.store(block, Relaxed);
+    }
+
+    /// The most recent density summaries, of whatever block size.
+    pub fn density(&self) -> Option<Arc<Density>> {
+        self.density.read().unwrap().clone()
+    }
+
+    /// Completes once the next density summaries are published. Take it
+    /// before asking for them so that none are missed.
+    pub fn next_density(&self) -> Notified<'_> {
+        self.densities.notified()
+    }
+
+    /// How the garden stopped changing, if it has, and the tick it did.
+    pub fn stagnation(&self) -> Option<(Stagnation, u64)> {
+        *self.stagnation.read().unwrap()
+    }
+
+    /// Changes of `stagnation` from now on.
+    pub fn stagnation_changes(&self) -> broadcast::Receiver<Change> {
+        self.stagnation_changes.subscribe()
+    }
+
+    /// Record the cell at (x, y) after every tick from now on.
+    pub fn pin(&self, x: u32, y: u32) -> Result<(), String> {
+        if x >= self.world_width || y >= self.world_width {
+            return Err(format!("({}, {}) is outside the world", x, y));
+        }
+        let mut watchpoints = self.watchpoints.lock().unwrap();
+        if watchpoints.iter().any(|w| w.x == x && w.y == y) {
+            return Ok(());
+        }
+        if watchpoints.len() == MAX_WATCHPOINTS {
+            return Err(format!(
+                "At most {} cells can be watched at once",
+                MAX_WATCHPOINTS
+            ));
+        }
+        watchpoints.push(Watchpoint::new(x, y));
+        Ok(())
+    }
+
+    /// Stop watching the cell at (x, y), dropping its history. False if it
+    /// was not watched.
+    pub fn unpin(&self, x: u32, y: u32) -> bool {
+        let mut watchpoints = self.watchpoints.lock().unwrap();
+        let count = watchpoints.len();
+        watchpoints.retain(|w| w.x != x || w.y != y);
+        watchpoints.len() != count
+    }
+
+    /// The history of every watched cell.
+    pub fn traces(&self) -> Vec<Trace> {
+        let watchpoints = self.watchpoints.lock().unwrap();
+        watchpoints
+            .iter()
+            .map(|w| w.trace(&self.branches))
+            .collect()
+    }
+
+    /// Every watched cell with its last `steps` steps, oldest first.
+    pub fn watched(&self, steps: usize) -> Vec<(u32, u32, Vec<Step>)> {
+        let watchpoints = self.watchpoints.lock().unwrap();
+        watchpoints
+            .iter()
+            .map(|w| {
+                let skip = w.history.len().saturating_sub(steps);
+                (w.x, w.y, w.history.iter().skip(skip).copied().collect())
+            })
+            .collect()
+    }
 }
 
 impl Runner {
-    pub fn new(world_width: u32) -> Runner {
+    /// Fails if the device cannot run a world `world_width` cells wide.
+    pub fn new(
+        world_width: u32,
+        topology: Topology,
+        terrain: &[Terrain],
+        seasons: Seasons,
+        shaders: Shaders,
+        residency: Residency,
+        readback_rate: u32,
+    ) -> Result<Runner, String> {
+        if world_width == 0 || world_width > u16::MAX as u32 {
+            return Err(format!("World width must be between 1 and {}", u16::MAX));
+        }
+        let cells = world_width as u64 * world_width as u64;
+
+        // Memory init.
+        let mut timing: JobTimingsBuilder = JobTimingsBuilder::new();
+        timing = timing.start_upload();
+        let vulkan = Rc::new(vkstate::init_vulkan());
+        let layout = shaders.layout;
+        let buffer_size: u64 = cells * layout.cell_size() as u64;
+
+        let terrain_size: u64 = std::mem::size_of_val(terrain) as u64;
+        // The tables are only written and read by the GPU. Rules that scan
+        // still get a token buffer so every shader shares one layout.
+        let tables_size: u64 = std::cmp::max(
+            shaders.channels as u64 * cells * std::mem::size_of::<i32>() as u64,
+            std::mem::size_of::<i32>() as u64,
+        );
+        let groups = dispatch_size(
+            &vkstate::device_limits(vulkan.clone()),
+            world_width,
+            std::cmp::max(buffer_size, tables_size),
+        )?;
+
+        // Only seed the world once the device is known to hold it.
         let mut state = vec![
             CellState {
                 concept: Concept::Soil,
@@ -100,154 +734,202 @@ impl Runner {
             }
         }
 
-        // Memory init.
-        let mut timing: JobTimingsBuilder = JobTimingsBuilder::new();
-        timing = timing.start_upload();
-        let shader_path = PathBuf::from("target/game.spv");
-        let vulkan = Rc::new(wyzoid::low::vkstate::init_vulkan());
-        let buffer_size: u64 = (state.len() * std::mem::size_of::<CellState>()) as u64;
-
-        // Make two buffers and bind them to GPU memory
-        let mut left_buffer = vkmem::VkBuffer::new(vulkan.clone(), buffer_size);
-        let mut right_buffer = vkmem::VkBuffer::new(vulkan.clone(), buffer_size);
-        let mut param_buffer =
-            vkmem::VkBuffer::new(vulkan.clone(), std::mem::size_of::<ShaderParams>() as u64);
-        let (mem_size, offsets) = vkmem::compute_non_overlapping_buffer_alignment(&vec![
-            &left_buffer,
-            &right_buffer,
-            &param_buffer,
-        ]);
-        let memory = vkmem::VkMem::find_mem(vulkan.clone(), mem_size)
-            .expect("[ERR] Could not find a memory type fitting our need.");
+        // The shaders are the only ones touching the cells, terrain and
+        // tables, so on discrete GPUs they stay in device-local memory and
+        // frames reach the host through a staging buffer.
+        let staged = match residency {
+            Residency::Auto => {
+                vkstate::device_type(vulkan.clone()) == vk::PhysicalDeviceType::DISCRETE_GPU
+            }
+            Residency::Device => true,
+            Residency::Host => false,
+        };
+        let transfer = vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST;
+        let storage = vk::BufferUsageFlags::STORAGE_BUFFER | transfer;
+        let mut left_buffer = vkmem::VkBuffer::with_usage(vulkan.clone(), buffer_size, storage);
+        let mut right_buffer = vkmem::VkBuffer::with_usage(vulkan.clone(), buffer_size, storage);
+        let mut terrain_buffer = vkmem::VkBuffer::with_usage(vulkan.clone(), terrain_size, storage);
+        let mut tables_buffer = vkmem::VkBuffer::new(vulkan.clone(), tables_size);
+        let mut param_buffers: Vec<vkmem::VkBuffer> = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                vkmem::VkBuffer::new(vulkan.clone(), std::mem::size_of::<ShaderParams>() as u64)
+            })
+            .collect();
+        let max_blocks = density::blocks_wide(world_width, density::MIN_BLOCK).pow(2);
+        let census_size = (world_width as u64 * 3
+            + (MAX_WATCHPOINTS * TRACE_WORDS) as u64
+            + max_blocks as u64 * DENSITY_WORDS as u64)
+            * std::mem::size_of::<u32>() as u64;
+        let mut census_buffers: Vec<vkmem::VkBuffer> = (0..FRAMES_IN_FLIGHT)
+            .map(|_| vkmem::VkBuffer::new(vulkan.clone(), census_size))
+            .collect();
+        let mut staging = if staged {
+            Some(vkmem::VkBuffer::with_usage(
+                vulkan.clone(),
+                buffer_size,
+                transfer,
+            ))
+        } else {
+            None
+        };
 
+        let device_buffers = vec![&left_buffer, &right_buffer, &terrain_buffer, &tables_buffer];
+        let (mem_size, offsets) = vkmem::compute_non_overlapping_buffer_alignment(&device_buffers);
+        let memory = vkmem::VkMem::find_mem_with(
+            vulkan.clone(),
+            mem_size,
+            if staged {
+                vk::MemoryPropertyFlags::DEVICE_LOCAL
+            } else {
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
+            },
+            vkmem::common_memory_type_bits(&device_buffers),
+        )
+        .ok_or("Could not allocate the cells, terrain and tables")?;
         left_buffer.bind(memory.mem, offsets[0]);
         right_buffer.bind(memory.mem, offsets[1]);
-        param_buffer.bind(memory.mem, offsets[2]);
+        terrain_buffer.bind(memory.mem, offsets[2]);
+        tables_buffer.bind(memory.mem, offsets[3]);
 
-        // Try mapping all three buffers here, and leave them mapped
-        let left_data: *mut CellState = unsafe {
-            vulkan
-                .device
-                .map_memory(
-                    memory.mem,
-                    left_buffer.offset,
-                    left_buffer.size,
-                    vk::MemoryMapFlags::empty(),
-                )
-                .expect("[ERR] Could not map memory.") as *mut CellState
-        };
-        unsafe {
-            std::ptr::copy_nonoverlapping(state.as_ptr(), left_data, state.len());
+        // Params, censuses and staging always live in host-visible memory.
+        let mut host_buffers: Vec<&vkmem::VkBuffer> = param_buffers.iter().collect();
+        host_buffers.extend(census_buffers.iter());
+        host_buffers.extend(staging.iter());
+        let (host_size, host_offsets) =
+            vkmem::compute_non_overlapping_buffer_alignment(&host_buffers);
+        let host_memory = vkmem::VkMem::find_mem_with(
+            vulkan.clone(),
+            host_size,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            vkmem::common_memory_type_bits(&host_buffers),
+        )
+        .ok_or("Could not allocate host-visible memory for params and readback")?;
+        let staging_offset = host_offsets[2 * FRAMES_IN_FLIGHT];
+        for (buffer, offset) in param_buffers
+            .iter_mut()
+            .chain(census_buffers.iter_mut())
+            .zip(&host_offsets)
+        {
+            buffer.bind(host_memory.mem, *offset);
+        }
+        if let Some(staging) = &mut staging {
+            staging.bind(host_memory.mem, staging_offset);
         }
-        let right_data: *mut CellState = unsafe {
+
+        // Map each host-visible allocation once and leave it mapped.
+        let map = |mem: &vkmem::VkMem| unsafe {
             vulkan
                 .device
-                .map_memory(
-                    memory.mem,
-                    right_buffer.offset,
-                    right_buffer.size,
-                    vk::MemoryMapFlags::empty(),
-                )
-                .expect("[ERR] Could not map memory.") as *mut CellState
+                .map_memory(mem.mem, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+                .expect("[ERR] Could not map memory.") as *mut u8
         };
-        let param_data: *mut ShaderParams = unsafe {
-            vulkan
-                .device
-                .map_memory(
-                    memory.mem,
-                    param_buffer.offset,
-                    param_buffer.size,
-                    vk::MemoryMapFlags::empty(),
-                )
-                .expect("[ERR] Could not map memory.") as *mut ShaderParams
+        let host_data = map(&host_memory);
+        let param_data: Vec<*mut ShaderParams> = host_offsets[..FRAMES_IN_FLIGHT]
+            .iter()
+            .map(|offset| unsafe { host_data.add(*offset as usize) as *mut ShaderParams })
+            .collect();
+        let census_data: Vec<*const u32> = host_offsets[FRAMES_IN_FLIGHT..2 * FRAMES_IN_FLIGHT]
+            .iter()
+            .map(|offset| unsafe { host_data.add(*offset as usize) as *const u32 })
+            .collect();
+        let readable: [*mut u8; 2] = unsafe {
+            if staged {
+                let staging_data = host_data.add(staging_offset as usize);
+                [staging_data, staging_data]
+            } else {
+                let data = map(&memory);
+                // The terrain never changes, so it is written once and left alone.
+                std::ptr::copy_nonoverlapping(
+                    terrain.as_ptr(),
+                    data.add(offsets[2] as usize) as *mut Terrain,
+                    terrain.len(),
+                );
+                [data.add(offsets[0] as usize), data.add(offsets[1] as usize)]
+            }
         };
-        unsafe {
-            std::ptr::copy_nonoverlapping(
-                vec![ShaderParams {
-                    world_width: world_width,
-                    flip: 0,
-                    mutations_size: 0,
-                    mutations: [Mutation {
-                        x: 0,
-                        y: 0,
-                        concept: Concept::Soil,
-                    }; 100],
-                }]
-                .as_ptr(),
-                param_data,
-                1,
-            );
-        }
         timing = timing.stop_upload();
 
         // Shaders
         timing = timing.start_shader();
 
-        // Create the shader, and bind to its layouts.
-        let shader = Rc::new(RefCell::new(vkshader::VkShader::new(
-            vulkan.clone(),
-            &shader_path,
-            CString::new("main").unwrap(),
-        )));
-        shader.borrow_mut().add_layout_binding(
-            0,
-            1,
-            vk::DescriptorType::STORAGE_BUFFER,
-            vk::ShaderStageFlags::COMPUTE,
-        );
-        shader.borrow_mut().add_layout_binding(
-            1,
-            1,
-            vk::DescriptorType::STORAGE_BUFFER,
-            vk::ShaderStageFlags::COMPUTE,
-        );
-        shader.borrow_mut().add_layout_binding(
-            2,
-            1,
-            vk::DescriptorType::STORAGE_BUFFER,
-            vk::ShaderStageFlags::COMPUTE,
-        );
-        shader.borrow_mut().create_pipeline_layout();
+        // Create the shaders, all bound to the same layouts so they can share
+        // one descriptor set.
+        let make_shader = |bytecode: Vec<u32>| {
+            let shader = Rc::new(RefCell::new(vkshader::VkShader::from_bytecode(
+                vulkan.clone(),
+                bytecode,
+                CString::new("main").unwrap(),
+            )));
+            for binding in 0..6 {
+                shader.borrow_mut().add_layout_binding(
+                    binding,
+                    1,
+                    vk::DescriptorType::STORAGE_BUFFER,
+                    vk::ShaderStageFlags::COMPUTE,
+                );
+            }
+            shader.borrow_mut().create_pipeline_layout();
+            shader
+        };
+        let shader = make_shader(shaders.rules);
         let pipeline_layout = shader.borrow().pipeline.unwrap();
         let compute_pipeline = vkpipeline::VkComputePipeline::new(vulkan.clone(), &shader.borrow());
+        let census_shader = make_shader(shaders.census);
+        let census_layout = census_shader.borrow().pipeline.unwrap();
+        let census_pipeline =
+            vkpipeline::VkComputePipeline::new(vulkan.clone(), &census_shader.borrow());
+        let density_shader = make_shader(shaders.density);
+        let density_layout = density_shader.borrow().pipeline.unwrap();
+        let density_pipeline =
+            vkpipeline::VkComputePipeline::new(vulkan.clone(), &density_shader.borrow());
+        let table_passes = shaders.tables.map(|(rows, columns)| {
+            let (rows, columns) = (make_shader(rows), make_shader(columns));
+            let passes = [
+                (
+                    vkpipeline::VkComputePipeline::new(vulkan.clone(), &rows.borrow()),
+                    rows.borrow().pipeline.unwrap(),
+                ),
+                (
+                    vkpipeline::VkComputePipeline::new(vulkan.clone(), &columns.borrow()),
+                    columns.borrow().pipeline.unwrap(),
+                ),
+            ];
+            (rows, columns, passes)
+        });
         let mut shader_descriptor = vkdescriptor::VkDescriptor::new(vulkan.clone(), shader.clone());
         let mut write_descriptor = vkdescriptor::VkWriteDescriptor::new(vulkan.clone());
 
-        shader_descriptor.add_pool_size(3, vk::DescriptorType::STORAGE_BUFFER);
-        shader_descriptor.create_pool(1);
-        shader_descriptor.create_set();
-
-        let desc_set: vk::DescriptorSet = *shader_descriptor.get_first_set().unwrap();
-        let mut buffers_nfos: Vec<Vec<vk::DescriptorBufferInfo>> = Vec::new();
-
-        // Add write descriptors for our two buffers
-        write_descriptor.add_buffer(left_buffer.buffer, 0, left_buffer.size);
-        buffers_nfos.push(vec![write_descriptor.buffer_descriptors[0]]);
-        write_descriptor.add_write_descriptors(
-            desc_set,
-            vk::DescriptorType::STORAGE_BUFFER,
-            &buffers_nfos[0],
-            0,
-            0,
-        );
-        write_descriptor.add_buffer(right_buffer.buffer, 0, right_buffer.size);
-        buffers_nfos.push(vec![write_descriptor.buffer_descriptors[1]]);
-        write_descriptor.add_write_descriptors(
-            desc_set,
-            vk::DescriptorType::STORAGE_BUFFER,
-            &buffers_nfos[1],
-            1,
-            0,
-        );
-        write_descriptor.add_buffer(param_buffer.buffer, 0, param_buffer.size);
-        buffers_nfos.push(vec![write_descriptor.buffer_descriptors[2]]);
-        write_descriptor.add_write_descriptors(
-            desc_set,
+        // One descriptor set per frame in flight, differing only in their
+        // params and census.
+        shader_descriptor.add_pool_size(
+            6 * FRAMES_IN_FLIGHT as u32,
             vk::DescriptorType::STORAGE_BUFFER,
-            &buffers_nfos[2],
-            2,
-            0,
         );
+        shader_descriptor.create_pool(FRAMES_IN_FLIGHT as u32);
+        let mut buffers_nfos: Vec<Vec<vk::DescriptorBufferInfo>> = Vec::new();
+        for (param_buffer, census_buffer) in param_buffers.iter().zip(&census_buffers) {
+            shader_descriptor.create_set();
+            let desc_set: vk::DescriptorSet = *shader_descriptor.set.last().unwrap();
+            let bindings = [
+                &left_buffer,
+                &right_buffer,
+                param_buffer,
+                &terrain_buffer,
+                &tables_buffer,
+                census_buffer,
+            ];
+            for (binding, buffer) in bindings.iter().enumerate() {
+                write_descriptor.add_buffer(buffer.buffer, 0, buffer.size);
+                buffers_nfos.push(vec![*write_descriptor.buffer_descriptors.last().unwrap()]);
+                write_descriptor.add_write_descriptors(
+                    desc_set,
+                    vk::DescriptorType::STORAGE_BUFFER,
+                    buffers_nfos.last().unwrap(),
+                    binding as u32,
+                    0,
+                );
+            }
+        }
 
         write_descriptor.update_descriptors_sets();
 
@@ -257,146 +939,761 @@ impl Runner {
         timing = timing.start_cmd();
         let mut cmd_pool = vkcmd::VkCmdPool::new(vulkan.clone());
 
-        let cmd_buffer = cmd_pool.create_cmd_buffer(vk::CommandBufferLevel::PRIMARY);
-        cmd_pool.begin_cmd(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT, cmd_buffer);
-        cmd_pool.bind_pipeline(
-            compute_pipeline.pipeline,
-            vk::PipelineBindPoint::COMPUTE,
-            cmd_buffer,
-        );
-        cmd_pool.bind_descriptor(
-            pipeline_layout,
-            vk::PipelineBindPoint::COMPUTE,
-            &shader_descriptor.set,
-            cmd_buffer,
-        );
+        let mut tick_cmds = Vec::new();
+        for (frame, (param_buffer, census_buffer)) in
+            param_buffers.iter().zip(&census_buffers).enumerate()
+        {
+            let cmd_buffer = cmd_pool.create_cmd_buffer(vk::CommandBufferLevel::PRIMARY);
+            tick_cmds.push(cmd_buffer);
+            cmd_pool.begin_cmd(vk::CommandBufferUsageFlags::empty(), cmd_buffer);
 
-        cmd_pool.dispatch((state.len() / 25) as u32, 1, 1, cmd_buffer);
+            // Build the tables from the previous tick before the rules read them.
+            if let Some((_, _, passes)) = &table_passes {
+                let table_groups = world_width.div_ceil(TABLE_GROUP_SIZE);
+                for (pass, layout) in passes.iter() {
+                    cmd_pool.bind_pipeline(
+                        pass.pipeline,
+                        vk::PipelineBindPoint::COMPUTE,
+                        cmd_buffer,
+                    );
+                    cmd_pool.bind_descriptor(
+                        *layout,
+                        vk::PipelineBindPoint::COMPUTE,
+                        &shader_descriptor.set[frame..frame + 1],
+                        cmd_buffer,
+                    );
+                    cmd_pool.dispatch(table_groups, 1, 1, cmd_buffer);
 
-        // Memory barrier
-        let mut buffer_barrier: Vec<vk::BufferMemoryBarrier> = Vec::new();
-        buffer_barrier.push(
-            vk::BufferMemoryBarrier::builder()
-                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
-                .dst_access_mask(vk::AccessFlags::SHADER_READ)
-                .buffer(left_buffer.buffer)
-                .size(vk::WHOLE_SIZE)
-                .build(),
-        );
-        buffer_barrier.push(
-            vk::BufferMemoryBarrier::builder()
-                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
-                .dst_access_mask(vk::AccessFlags::SHADER_READ)
-                .buffer(right_buffer.buffer)
-                .size(vk::WHOLE_SIZE)
-                .build(),
-        );
-        buffer_barrier.push(
-            vk::BufferMemoryBarrier::builder()
+                    let tables_barrier = [vk::BufferMemoryBarrier::builder()
+                        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                        .dst_access_mask(
+                            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                        )
+                        .buffer(tables_buffer.buffer)
+                        .size(vk::WHOLE_SIZE)
+                        .build()];
+                    unsafe {
+                        vulkan.device.cmd_pipeline_barrier(
+                            cmd_pool.cmd_buffers[cmd_buffer],
+                            vk::PipelineStageFlags::COMPUTE_SHADER,
+                            vk::PipelineStageFlags::COMPUTE_SHADER,
+                            vk::DependencyFlags::empty(),
+                            &[],
+                            &tables_barrier,
+                            &[],
+                        );
+                    }
+                }
+            }
+
+            cmd_pool.bind_pipeline(
+                compute_pipeline.pipeline,
+                vk::PipelineBindPoint::COMPUTE,
+                cmd_buffer,
+            );
+            cmd_pool.bind_descriptor(
+                pipeline_layout,
+                vk::PipelineBindPoint::COMPUTE,
+                &shader_descriptor.set[frame..frame + 1],
+                cmd_buffer,
+            );
+
+            cmd_pool.dispatch(groups, groups, 1, cmd_buffer);
+
+            // Memory barrier
+            let mut buffer_barrier: Vec<vk::BufferMemoryBarrier> = Vec::new();
+            buffer_barrier.push(
+                vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .buffer(left_buffer.buffer)
+                    .size(vk::WHOLE_SIZE)
+                    .build(),
+            );
+            buffer_barrier.push(
+                vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .buffer(right_buffer.buffer)
+                    .size(vk::WHOLE_SIZE)
+                    .build(),
+            );
+            buffer_barrier.push(
+                vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .buffer(param_buffer.buffer)
+                    .size(vk::WHOLE_SIZE)
+                    .build(),
+            );
+            buffer_barrier.push(
+                vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_READ)
+                    .dst_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .buffer(tables_buffer.buffer)
+                    .size(vk::WHOLE_SIZE)
+                    .build(),
+            );
+
+            unsafe {
+                vulkan.device.cmd_pipeline_barrier(
+                    cmd_pool.cmd_buffers[cmd_buffer],
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &buffer_barrier,
+                    &[],
+                );
+            }
+
+            // Census of the cells just written, read by the host once the
+            // tick is done.
+            cmd_pool.bind_pipeline(
+                census_pipeline.pipeline,
+                vk::PipelineBindPoint::COMPUTE,
+                cmd_buffer,
+            );
+            cmd_pool.bind_descriptor(
+                census_layout,
+                vk::PipelineBindPoint::COMPUTE,
+                &shader_descriptor.set[frame..frame + 1],
+                cmd_buffer,
+            );
+            cmd_pool.dispatch(world_width.div_ceil(CENSUS_GROUP_SIZE), 1, 1, cmd_buffer);
+
+            // Density summaries after the census, which every invocation
+            // skips unless the tick's params ask for them.
+            cmd_pool.bind_pipeline(
+                density_pipeline.pipeline,
+                vk::PipelineBindPoint::COMPUTE,
+                cmd_buffer,
+            );
+            cmd_pool.bind_descriptor(
+                density_layout,
+                vk::PipelineBindPoint::COMPUTE,
+                &shader_descriptor.set[frame..frame + 1],
+                cmd_buffer,
+            );
+            cmd_pool.dispatch(max_blocks.div_ceil(DENSITY_GROUP_SIZE), 1, 1, cmd_buffer);
+            let census_barrier = [vk::BufferMemoryBarrier::builder()
                 .src_access_mask(vk::AccessFlags::SHADER_WRITE)
-                .dst_access_mask(vk::AccessFlags::SHADER_READ)
-                .buffer(param_buffer.buffer)
+                .dst_access_mask(vk::AccessFlags::HOST_READ)
+                .buffer(census_buffer.buffer)
                 .size(vk::WHOLE_SIZE)
-                .build(),
-        );
+                .build()];
+            unsafe {
+                vulkan.device.cmd_pipeline_barrier(
+                    cmd_pool.cmd_buffers[cmd_buffer],
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::HOST,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &census_barrier,
+                    &[],
+                );
+            }
 
-        unsafe {
-            vulkan.device.cmd_pipeline_barrier(
-                cmd_pool.cmd_buffers[cmd_buffer],
-                vk::PipelineStageFlags::COMPUTE_SHADER,
-                vk::PipelineStageFlags::COMPUTE_SHADER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &buffer_barrier,
-                &[],
-            );
+            cmd_pool.end_cmd(cmd_buffer);
         }
 
-        cmd_pool.end_cmd(cmd_buffer);
+        let mut readback_cmds = Vec::new();
+        if let Some(staging) = &staging {
+            for written in [&left_buffer, &right_buffer].iter() {
+                let readback_cmd = cmd_pool.create_cmd_buffer(vk::CommandBufferLevel::PRIMARY);
+                cmd_pool.begin_cmd(vk::CommandBufferUsageFlags::empty(), readback_cmd);
+                record_copy(
+                    &vulkan,
+                    &cmd_pool,
+                    written.buffer,
+                    staging.buffer,
+                    buffer_size,
+                    readback_cmd,
+                );
+                cmd_pool.end_cmd(readback_cmd);
+                readback_cmds.push(readback_cmd);
+            }
+        }
         timing = timing.stop_cmd();
 
+        let copy_cmd = cmd_pool.create_cmd_buffer(vk::CommandBufferLevel::PRIMARY);
+
         // Execution
         let fence = vkfence::VkFence::new(vulkan.clone(), false);
-        return Runner {
+        let fences = (0..FRAMES_IN_FLIGHT)
+            .map(|_| vkfence::VkFence::new(vulkan.clone(), false))
+            .collect();
+        let terrain_handle = terrain_buffer.buffer;
+        let mut runner = Runner {
             vulkan,
+            cells: [left_buffer, right_buffer],
+            staging,
+            _buffers: vec![terrain_buffer, tables_buffer]
+                .into_iter()
+                .chain(param_buffers)
+                .chain(census_buffers)
+                .collect(),
             _memory: memory,
+            _host_memory: host_memory,
             game_state: Arc::new(GameState {
-                game_data: AtomicPtr::new(left_data),
-                game_size: AtomicI32::new((world_width * world_width) as i32),
+                frame: RwLock::new(Arc::new(Frame {
+                    tick: 0,
+                    publication: 0,
+                    layout,
+                    bytes: vec![],
+                })),
+                tick: AtomicU64::new(0),
+                world_width,
+                topology,
+                terrain: Arc::new(terrain.to_vec()),
+                seasons,
+                frame_wanted: AtomicBool::new(false),
+                published: Notify::new(),
+                stagnation: RwLock::new(None),
+                stagnation_changes: broadcast::channel(STAGNATION_CHANGES).0,
+                clusters: Mutex::new((Weak::new(), Arc::new(Vec::new()))),
+                levels: Mutex::new((Weak::new(), HashMap::new())),
+                branches: shaders.branches,
+                watchpoints: Mutex::new(Vec::new()),
+                density_wanted: AtomicU32::new(0),
+                density: RwLock::new(None),
+                densities: Notify::new(),
             }),
             world_width,
-            timing,
+            topology,
+            layout,
             fence,
             cmd_pool,
-            flip: 0,
-            left_data,
-            right_data,
+            copy_cmd,
+            tick_cmds,
+            fences,
             param_data,
+            census_data,
+            in_flight: (0..FRAMES_IN_FLIGHT).map(|_| None).collect(),
+            next_frame: 0,
+            submitted: 0,
+            last_timing: timing,
+            timing_log: None,
+            readback_cmds,
+            flip: 0,
+            readable,
+            spare: None,
+            publications: 0,
+            readback_interval: if readback_rate == 0 {
+                Duration::from_secs(0)
+            } else {
+                Duration::from_secs(1) / readback_rate
+            },
+            next_readback: Instant::now(),
             paused: false,
-            mutations: Arc::new(Mutex::new(vec![])),
+            overrides: Vec::new(),
+            watch: Watch::new(stagnation::MAX_PERIOD),
+            reseed: None,
+            mutations: Arc::new(Mutex::new(VecDeque::new())),
+        };
+
+        if let Some(staging) = runner.staging.as_ref().map(|staging| staging.buffer) {
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    terrain.as_ptr(),
+                    runner.readable[0] as *mut Terrain,
+                    terrain.len(),
+                );
+            }
+            runner.copy_now(staging, terrain_handle, terrain_size);
+        }
+        runner.load(&state);
+        Ok(runner)
+    }
+
+    fn queue(&self) -> vk::Queue {
+        unsafe {
+            self.vulkan
+                .device
+                .get_device_queue(self.vulkan.queue_family_index, 0)
+        }
+    }
+
+    /// Run a single buffer copy right away, waiting for it to finish.
+    fn copy_now(&mut self, src: vk::Buffer, dst: vk::Buffer, size: u64) {
+        let copy_cmd = self.copy_cmd;
+        self.cmd_pool
+            .begin_cmd(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT, copy_cmd);
+        record_copy(&self.vulkan, &self.cmd_pool, src, dst, size, copy_cmd);
+        self.cmd_pool.end_cmd(copy_cmd);
+        self.cmd_pool
+            .submit_buffers(self.queue(), &[copy_cmd], Some(self.fence.fence));
+        wait_for(&self.fence);
+    }
+
+    /// Publish the last finished tick, which wrote `written` and must already
+    /// be readable by the host.
+    fn publish(&mut self, written: usize) {
+        let size = (self.world_width * self.world_width) as usize * self.layout.cell_size();
+        let current = unsafe { std::slice::from_raw_parts(self.readable[written], size) };
+        let mut bytes = match self.spare.take().map(Arc::try_unwrap) {
+            Some(Ok(frame)) => frame.bytes,
+            _ => Vec::with_capacity(size),
+        };
+        bytes.clear();
+        bytes.extend_from_slice(current);
+
+        self.publications += 1;
+        let frame = Arc::new(Frame {
+            tick: self.game_state.tick.load(Relaxed),
+            publication: self.publications,
+            layout: self.layout,
+            bytes,
+        });
+        let mut published = self.game_state.frame.write().unwrap();
+        self.spare = Some(std::mem::replace(&mut *published, frame));
+        self.game_state.published.notify_waiters();
+    }
+
+    /// Wait for the tick in flight on `frame`, if any, and publish it if wanted.
+    fn retire(&mut self, frame: usize) {
+        if let Some(in_flight) = self.in_flight[frame].take() {
+            wait_for(&self.fences[frame]);
+            let mut timing = in_flight.timing.stop_execution();
+            let tick = self.game_state.tick.fetch_add(1, Relaxed) + 1;
+            if let Some(change) = self.watch.observe(tick, self.census(frame)) {
+                match self.watch.current() {
+                    Some((stagnation, _)) => {
+                        println!("Tick {}: the garden is {}", tick, stagnation)
+                    }
+                    None => println!("Tick {}: the garden is changing again", tick),
+                }
+                self.stagnate(change);
+            }
+            if !in_flight.watched.is_empty() {
+                self.record_traces(frame, tick, &in_flight.watched);
+            }
+            if in_flight.density_block != 0 {
+                self.publish_density(frame, tick, in_flight.density_block);
+            }
+            if in_flight.publish {
+                timing = timing.start_download();
+                self.publish(in_flight.written);
+                timing = timing.stop_download();
+            }
+            if let Some(log) = &mut self.timing_log {
+                log.push(timing.build());
+            }
+            self.last_timing = timing;
+        }
+    }
+
+    /// Sum up the census rows of the finished tick on `frame`.
+    fn census(&self, frame: usize) -> Census {
+        let rows = unsafe {
+            std::slice::from_raw_parts(self.census_data[frame], 3 * self.world_width as usize)
+        };
+        let (mut low, mut high, mut living) = (0u32, 0u32, 0u64);
+        for row in rows.chunks_exact(3) {
+            low = low.wrapping_add(row[0]);
+            high = high.wrapping_add(row[1]);
+            living += row[2] as u64;
+        }
+        Census {
+            hash: (high as u64) << 32 | low as u64,
+            living,
+        }
+    }
+
+    /// Add the traces of the finished tick on `frame` to the history of the
+    /// cells still watched.
+    fn record_traces(&self, frame: usize, tick: u64, watched: &[(u32, u32)]) {
+        let traces = unsafe {
+            std::slice::from_raw_parts(
+                self.census_data[frame].add(3 * self.world_width as usize),
+                watched.len() * TRACE_WORDS,
+            )
+        };
+        let mut watchpoints = self.game_state.watchpoints.lock().unwrap();
+        for ((x, y), trace) in watched.iter().zip(traces.chunks_exact(TRACE_WORDS)) {
+            if let Some(watchpoint) = watchpoints.iter_mut().find(|w| w.x == *x && w.y == *y) {
+                watchpoint.record(Step {
+                    tick,
+                    cell: CellState {
+                        concept: Concept::ALL[trace[0] as usize],
+                        blood: trace[1] as i32,
+                        joy: trace[2] as i32,
+                    },
+                    fired: trace[3],
+                    mutated: trace[4] != 0,
+                });
+            }
+        }
+    }
+
+    /// Publish the density summaries of the finished tick on `frame`.
+    fn publish_density(&self, frame: usize, tick: u64, block: u32) {
+        let blocks = density::blocks_wide(self.world_width, block).pow(2) as usize;
+        let words = unsafe {
+            std::slice::from_raw_parts(
+                self.census_data[frame]
+                    .add(3 * self.world_width as usize + MAX_WATCHPOINTS * TRACE_WORDS),
+                blocks * DENSITY_WORDS,
+            )
         };
+        let density = Density::read(tick, block, self.world_width, words);
+        *self.game_state.density.write().unwrap() = Some(Arc::new(density));
+        self.game_state.densities.notify_waiters();
+    }
+
+    /// Publish how the garden stagnates after `change`.
+    fn stagnate(&mut self, change: Change) {
+        *self.game_state.stagnation.write().unwrap() = self.watch.current();
+        // Nobody may be following the garden.
+        let _ = self.game_state.stagnation_changes.send(change);
+    }
+
+    /// Look for cycles up to `max_period` ticks long, and reseed the garden
+    /// once stagnant for `reseed` ticks if given.
+    pub fn watch_stagnation(&mut self, max_period: usize, reseed: Option<(u64, Reseed)>) {
+        self.watch = Watch::new(max_period);
+        self.reseed = reseed;
+    }
+
+    /// Wait for every tick in flight, oldest first.
+    pub fn finish(&mut self) {
+        for i in 0..FRAMES_IN_FLIGHT {
+            self.retire((self.next_frame + i) % FRAMES_IN_FLIGHT);
+        }
+    }
+
+    /// Keep the timings of every tick finished from now on, until taken.
+    pub fn record_timings(&mut self) {
+        self.timing_log = Some(Vec::new());
+    }
+
+    /// Timings of the ticks finished since recording started or the last call.
+    pub fn take_timings(&mut self) -> Vec<JobTimings> {
+        self.timing_log
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Copy of the most recently completed tick.
+    pub fn snapshot(&mut self) -> Vec<CellState> {
+        self.finish();
+        if let Some(staging) = self.staging.as_ref().map(|staging| staging.buffer) {
+            let current = &self.cells[self.flip as usize];
+            let (buffer, size) = (current.buffer, current.size);
+            self.copy_now(buffer, staging, size);
+        }
+        self.publish(self.flip as usize);
+        self.game_state.frame().cells().collect()
+    }
+
+    /// Replace the current tick's cells, e.g. to start from a prepared world.
+    pub fn load(&mut self, cells: &[CellState]) {
+        let size = (self.world_width * self.world_width) as usize;
+        if cells.len() != size {
+            panic!(
+                "[ERR] Expected {} cells to load, got {}.",
+                size,
+                cells.len()
+            );
+        }
+        self.finish();
+        let current = self.flip as usize;
+        let bytes = self.layout.encode_all(cells);
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), self.readable[current], bytes.len());
+        }
+        if let Some(staging) = self.staging.as_ref().map(|staging| staging.buffer) {
+            let (buffer, size) = (self.cells[current].buffer, self.cells[current].size);
+            self.copy_now(staging, buffer, size);
+        }
+        self.publish(current);
+    }
+
+    /// Run `ticks` ticks on the GPU, checking each against the CPU evaluator.
+    pub fn verify_rules(
+        &mut self,
+        rules: &RuleSet,
+        terrain: &[Terrain],
+        ticks: u32,
+    ) -> Result<(), String> {
+        for _ in 0..ticks {
+            let before = self.snapshot();
+            let tick = self.game_state.tick.load(Relaxed);
+            let expected = rules.step(
+                &World {
+                    cells: &before,
+                    terrain,
+                    width: self.world_width,
+                    topology: self.topology,
+                    season: self.rules_at(tick),
+                    tick: tick as u32,
+                    layout: self.layout,
+                },
+                &[],
+            );
+
+            self.execute();
+            let actual = self.snapshot();
+            if let Some(idx) = (0..actual.len()).find(|&i| actual[i] != expected[i]) {
+                return Err(format!(
+                    "GPU and CPU rules disagree at tick {} on cell ({}, {})",
+                    tick,
+                    idx as u32 % self.world_width,
+                    idx as u32 / self.world_width
+                ));
+            }
+        }
+        Ok(())
     }
 
     pub fn toggle_pause(&mut self) {
         self.paused = !self.paused;
     }
 
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Run `ticks` ticks now, paused or not, and wait for them.
+    pub fn step(&mut self, ticks: u32) {
+        let paused = std::mem::replace(&mut self.paused, false);
+        for _ in 0..ticks {
+            self.execute();
+        }
+        self.paused = paused;
+        self.finish();
+    }
+
+    /// Start over from `cells` at tick 0, forgetting how the garden stagnated.
+    pub fn reset(&mut self, cells: &[CellState]) {
+        self.finish();
+        self.game_state.tick.store(0, Relaxed);
+        self.submitted = 0;
+        if let Some(change) = self.watch.clear(0) {
+            self.stagnate(change);
+        }
+        self.load(cells);
+    }
+
+    /// Set a rule parameter, named as in season specs, for every tick from
+    /// now on whatever the season.
+    pub fn override_rule(&mut self, key: &str, value: i32) -> Result<(), String> {
+        set_rule(&mut SeasonRules::default(), key, value)?;
+        self.overrides.retain(|(existing, _)| existing != key);
+        self.overrides.push((key.to_string(), value));
+        Ok(())
+    }
+
+    /// Rule parameters set with `override_rule`, oldest first.
+    pub fn overrides(&self) -> &[(String, i32)] {
+        &self.overrides
+    }
+
+    /// The rule parameters of `tick`.
+    fn rules_at(&self, tick: u64) -> SeasonRules {
+        let mut rules = self.game_state.seasons.rules_at(tick);
+        for (key, value) in self.overrides.iter() {
+            set_rule(&mut rules, key, *value).unwrap();
+        }
+        rules
+    }
+
+    /// Submit the next tick without waiting for it, once the frame it reuses
+    /// has finished. Call `finish` to wait for everything submitted.
     pub fn execute(&mut self) {
         if self.paused {
             return;
         };
+        let frame = self.next_frame;
+        self.retire(frame);
+
+        if let (Some((after, reseed)), Some((stagnation, since))) =
+            (self.reseed, self.watch.current())
         {
-            let mut mutations = self.mutations.lock().unwrap();
-            let mutation_count = mutations.len();
-            let queued_mutations =
-                mutations.splice(0..(std::cmp::min(mutation_count, 100)), vec![]);
-            let mut m_array = [Mutation {
-                x: 0,
-                y: 0,
-                concept: Concept::Soil,
-            }; 100];
+            let tick = self.game_state.tick.load(Relaxed);
+            if tick - since >= after {
+                let mut cells = self.snapshot();
+                reseed.apply(&mut cells);
+                self.load(&cells);
+                if let Some(change) = self.watch.clear(tick) {
+                    self.stagnate(change);
+                }
+                println!(
+                    "Tick {}: reseeded the garden, {} for {} ticks",
+                    tick,
+                    stagnation,
+                    tick - since
+                );
+            }
+        }
+
+        let mut timing = JobTimingsBuilder::new().start_upload();
+        let watched: Vec<(u32, u32)> = self
+            .game_state
+            .watchpoints
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|w| (w.x, w.y))
+            .collect();
+        let density_block = self.game_state.density_wanted.swap(0, Relaxed);
+        {
+            let queued_mutations = next_mutations(&mut self.mutations.lock().unwrap());
+            let mut m_array =
+                [ShaderMutation::from(Mutation::plant(0, 0, Concept::Soil)); MAX_MUTATIONS];
             let m_count = queued_mutations.len() as u32;
-            for (i, m) in queued_mutations.enumerate() {
-                m_array[i] = m;
+            for (i, m) in queued_mutations.into_iter().enumerate() {
+                m_array[i] = m.into();
             }
 
+            let mut watches = [0; MAX_WATCHPOINTS];
+            for (watch, (x, y)) in watches.iter_mut().zip(&watched) {
+                *watch = x + y * self.world_width;
+            }
+
+            let tick = self.submitted;
             unsafe {
                 std::ptr::copy_nonoverlapping(
                     vec![ShaderParams {
                         world_width: self.world_width,
                         flip: self.flip,
+                        topology: self.topology,
+                        tick: tick as u32,
+                        season: self.rules_at(tick),
                         mutations_size: m_count,
                         mutations: m_array,
+                        watches_size: watched.len() as u32,
+                        watches,
+                        density_block,
                     }]
                     .as_ptr(),
-                    self.param_data,
+                    self.param_data[frame],
                     1,
                 );
             }
         }
+        timing = timing.stop_upload();
 
-        self.timing = self.timing.start_execution();
-        let queue = unsafe {
-            self.vulkan
-                .device
-                .get_device_queue(self.vulkan.queue_family_index, 0)
-        };
-        self.cmd_pool.submit(queue, Some(self.fence.fence));
+        // Publish the cells this tick writes only when someone wants them,
+        // and never while another publication is in flight since they would
+        // share the staging buffer.
+        let now = Instant::now();
+        let publishing = self
+            .in_flight
+            .iter()
+            .any(|in_flight| in_flight.as_ref().is_some_and(|f| f.publish));
+        let publish = !publishing
+            && now >= self.next_readback
+            && self.game_state.frame_wanted.swap(false, Relaxed);
+        let written = 1 - self.flip as usize;
 
-        while self.fence.status() != vkfence::FenceStates::SIGNALED {
-            self.fence.wait(1 * 1000 * 1000 * 1000);
+        timing = timing.start_execution();
+        if publish && self.staging.is_some() {
+            self.cmd_pool.submit_buffers(
+                self.queue(),
+                &[self.tick_cmds[frame], self.readback_cmds[written]],
+                Some(self.fences[frame].fence),
+            );
+        } else {
+            self.cmd_pool.submit_buffers(
+                self.queue(),
+                &[self.tick_cmds[frame]],
+                Some(self.fences[frame].fence),
+            );
         }
-        self.fence.reset();
-        self.timing = self.timing.stop_execution();
+        if publish {
+            self.next_readback = now + self.readback_interval;
+        }
+        self.in_flight[frame] = Some(InFlight {
+            written,
+            publish,
+            timing,
+            watched,
+            density_block,
+        });
         self.flip = if self.flip == 0 { 1 } else { 0 };
+        self.submitted += 1;
+        self.next_frame = (frame + 1) % FRAMES_IN_FLIGHT;
+    }
+}
 
-        self.game_state.game_data.store(
-            if self.flip == 0 {
-                self.left_data
-            } else {
-                self.right_data
-            },
-            Relaxed,
-        );
+fn wait_for(fence: &vkfence::VkFence) {
+    while fence.status() != vkfence::FenceStates::SIGNALED {
+        fence.wait(1 * 1000 * 1000 * 1000);
+    }
+    fence.reset();
+}
+
+/// Record a buffer copy ordered after the compute passes before it and
+/// visible to the shaders and host after it.
+fn record_copy(
+    vulkan: &VulkanState,
+    cmd_pool: &vkcmd::VkCmdPool,
+    src: vk::Buffer,
+    dst: vk::Buffer,
+    size: u64,
+    cmd_buffer: usize,
+) {
+    let barrier = |src_stage, src_access, dst_stage, dst_access| {
+        let memory_barrier = [vk::MemoryBarrier::builder()
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access)
+            .build()];
+        unsafe {
+            vulkan.device.cmd_pipeline_barrier(
+                cmd_pool.cmd_buffers[cmd_buffer],
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &memory_barrier,
+                &[],
+                &[],
+            );
+        }
+    };
+
+    barrier(
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::AccessFlags::SHADER_WRITE,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE,
+    );
+    cmd_pool.copy_buffer(src, dst, size, cmd_buffer);
+    barrier(
+        vk::PipelineStageFlags::TRANSFER,
+        vk::AccessFlags::TRANSFER_WRITE,
+        vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::HOST,
+        vk::AccessFlags::SHADER_READ | vk::AccessFlags::HOST_READ,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(size: usize) -> Vec<Mutation> {
+        (0..size as u32)
+            .map(|x| Mutation::plant(x, 0, Concept::Rose))
+            .collect()
+    }
+
+    #[test]
+    fn ticks_take_whole_batches_in_order() {
+        let mut queue = VecDeque::from(vec![batch(60), batch(30), batch(20), batch(5)]);
+        assert_eq!(next_mutations(&mut queue).len(), 90);
+        assert_eq!(next_mutations(&mut queue).len(), 25);
+        assert!(next_mutations(&mut queue).is_empty());
+    }
+
+    #[test]
+    fn a_full_batch_fills_a_tick() {
+        let mut queue = VecDeque::from(vec![batch(MAX_MUTATIONS), batch(1)]);
+        assert_eq!(next_mutations(&mut queue).len(), MAX_MUTATIONS);
+        assert_eq!(next_mutations(&mut queue).len(), 1);
     }
 }
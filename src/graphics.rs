@@ -1,17 +1,23 @@
 extern crate sdl2;
 
+use crate::game::CellState;
 use crate::game::Concept;
+use crate::game::Terrain;
+use crate::game::Topology;
 use crate::GameState;
 use sdl2::event::Event;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::ttf::Font;
 use sdl2::video::{Window, WindowContext};
 use std::path::Path;
-use std::sync::atomic::Ordering::Relaxed;
 use std::sync::Arc;
 
 const SOIL_COLOR: Color = Color::RGB(53, 48, 40);
+const MEADOW_COLOR: Color = Color::RGB(48, 58, 36);
+const ROCK_COLOR: Color = Color::RGB(74, 72, 70);
+const WATER_COLOR: Color = Color::RGB(36, 52, 72);
 const SUNFLOWER_COLOR: Color = Color::RGB(100, 87, 39);
 const ROSE_COLOR: Color = Color::RGB(191, 67, 66);
 const DOGWOOD_COLOR: Color = Color::RGB(234, 213, 230);
@@ -21,6 +27,12 @@ const DOGWOOD_COLOR_RUIN: Color = Color::RGB(69, 63, 57);
 const ELDER_COLOR: Color = Color::RGB(255, 223, 100);
 
 const FPS_COLOR: Color = Color::RGB(208, 240, 192);
+const SEASON_COLOR: Color = Color::RGB(208, 240, 192);
+const STAGNATION_COLOR: Color = Color::RGB(191, 67, 66);
+const WATCH_COLOR: Color = Color::RGB(120, 200, 230);
+
+/// Latest steps of each watched cell listed in the window.
+const TRACE_STEPS: usize = 3;
 
 pub struct Graphics {
   sdl_context: sdl2::Sdl,
@@ -30,6 +42,8 @@ pub struct Graphics {
   world_width: u32,
   frame_rate: u32,
   show_fps: bool,
+  topology: Topology,
+  terrain: Arc<Vec<Terrain>>,
 }
 
 pub struct Textures<'a> {
@@ -42,12 +56,104 @@ pub struct Textures<'a> {
   elder: Texture<'a>,
 }
 
+/// Dogwood fades from fresh to ruin as its blood rises.
+fn dogwood_band(blood: i32) -> usize {
+  if blood < -35 {
+    0
+  } else if blood < -20 {
+    1
+  } else if blood < -5 {
+    2
+  } else {
+    3
+  }
+}
+
+/// Colour of the bare ground.
+pub fn terrain_color(ground: Terrain) -> Color {
+  match ground {
+    Terrain::Plain => SOIL_COLOR,
+    Terrain::Meadow => MEADOW_COLOR,
+    Terrain::Rock => ROCK_COLOR,
+    Terrain::Water => WATER_COLOR,
+  }
+}
+
+/// Colour of a concept on plain ground, Dogwood at its freshest.
+pub fn concept_color(concept: Concept) -> Color {
+  match concept {
+    Concept::Soil => SOIL_COLOR,
+    Concept::Sunflower => SUNFLOWER_COLOR,
+    Concept::Rose => ROSE_COLOR,
+    Concept::Dogwood => DOGWOOD_COLOR,
+    Concept::Elder | Concept::Thistle => ELDER_COLOR,
+  }
+}
+
+/// Colour the window draws a cell over `ground` in.
+pub fn cell_color(cell: &CellState, ground: Terrain) -> Color {
+  match cell.concept {
+    Concept::Soil => terrain_color(ground),
+    Concept::Dogwood => [
+      DOGWOOD_COLOR,
+      DOGWOOD_COLOR_FADED,
+      DOGWOOD_COLOR_RELIC,
+      DOGWOOD_COLOR_RUIN,
+    ][dogwood_band(cell.blood)],
+    concept => concept_color(concept),
+  }
+}
+
+/// Hexagonal worlds shift odd rows right by half a cell.
+pub fn row_shift(row: u32, square_size: u32, topology: Topology) -> u32 {
+  if topology == Topology::Hexagonal && row % 2 == 1 {
+    square_size / 2
+  } else {
+    0
+  }
+}
+
+/// Window rectangle covered by the cell at index `i`.
+pub fn cell_rect(i: u32, world_width: u32, square_size: u32, topology: Topology) -> Rect {
+  let row = i / world_width;
+  Rect::new(
+    ((i % world_width) * square_size + row_shift(row, square_size, topology)) as i32,
+    (row * square_size) as i32,
+    square_size,
+    square_size,
+  )
+}
+
+/// Cell coordinates under a window position, the inverse of `cell_rect`.
+/// None outside the world, as in the margin hex worlds leave beside their
+/// shifted rows.
+pub fn cell_at(
+  x: i32,
+  y: i32,
+  world_width: u32,
+  square_size: u32,
+  topology: Topology,
+) -> Option<(u32, u32)> {
+  if x < 0 || y < 0 {
+    return None;
+  }
+  let row = y as u32 / square_size;
+  let column = (x as u32).checked_sub(row_shift(row, square_size, topology))? / square_size;
+  if column < world_width && row < world_width {
+    Some((column, row))
+  } else {
+    None
+  }
+}
+
 impl Graphics {
   pub fn new(
     square_size: u32,
     world_width: u32,
     frame_rate: u32,
     show_fps: bool,
+    topology: Topology,
+    terrain: Arc<Vec<Terrain>>,
   ) -> Result<Graphics, String> {
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
@@ -59,7 +165,7 @@ impl Graphics {
     let window = video_subsystem
       .window(
         "bit-garden",
-        square_size * world_width,
+        square_size * world_width + row_shift(1, square_size, topology),
         square_size * world_width,
       )
       .position_centered()
@@ -96,9 +202,47 @@ impl Graphics {
       world_width: world_width,
       frame_rate,
       show_fps,
+      topology,
+      terrain,
     });
   }
 
+  fn background_texture<'a>(
+    canvas: &mut Canvas<Window>,
+    texture_creator: &'a TextureCreator<WindowContext>,
+    terrain: &[Terrain],
+    square_size: u32,
+    world_width: u32,
+    topology: Topology,
+  ) -> Result<Texture<'a>, String> {
+    let mut background = texture_creator
+      .create_texture_target(
+        None,
+        square_size * world_width + row_shift(1, square_size, topology),
+        square_size * world_width,
+      )
+      .map_err(|e| e.to_string())?;
+
+    canvas
+      .with_texture_canvas(&mut background, |texture_canvas| {
+        texture_canvas.set_draw_color(SOIL_COLOR);
+        texture_canvas.clear();
+        for (i, ground) in terrain.iter().enumerate() {
+          let i = i as u32;
+          let tint = match ground {
+            Terrain::Plain => continue,
+            _ => terrain_color(*ground),
+          };
+          texture_canvas.set_draw_color(tint);
+          texture_canvas
+            .fill_rect(cell_rect(i, world_width, square_size, topology))
+            .expect("could not draw point");
+        }
+      })
+      .map_err(|e| e.to_string())?;
+    Ok(background)
+  }
+
   fn dummy_texture<'a>(
     canvas: &mut Canvas<Window>,
     texture_creator: &'a TextureCreator<WindowContext>,
@@ -200,6 +344,29 @@ impl Graphics {
     })
   }
 
+  /// Render a line of text with its top left corner at (0, `y`).
+  fn draw_text(
+    canvas: &mut Canvas<Window>,
+    texture_creator: &TextureCreator<WindowContext>,
+    font: &Font,
+    text: &str,
+    color: Color,
+    y: i32,
+  ) -> Result<(), String> {
+    let surface = font
+      .render(text)
+      .blended(color)
+      .map_err(|e| e.to_string())?;
+    let texture = texture_creator
+      .create_texture_from_surface(&surface)
+      .map_err(|e| e.to_string())?;
+    canvas.copy(
+      &texture,
+      None,
+      Some(Rect::new(0, y, surface.width(), surface.height())),
+    )
+  }
+
   pub fn run<FEvent>(&mut self, state: Arc<GameState>, mut on_event: FEvent) -> Result<(), String>
   where
     FEvent: FnMut(Event) -> bool,
@@ -216,6 +383,15 @@ impl Graphics {
     let textures =
       Graphics::dummy_texture(&mut self.canvas, &self.texture_creator, self.square_size)
         .expect("could not build square texture");
+    let background = Graphics::background_texture(
+      &mut self.canvas,
+      &self.texture_creator,
+      &self.terrain,
+      self.square_size,
+      self.world_width,
+      self.topology,
+    )
+    .expect("could not build terrain texture");
 
     'exit: loop {
       // get the inputs here
@@ -225,126 +401,110 @@ impl Graphics {
         }
       }
 
-      self.canvas.set_draw_color(SOIL_COLOR);
-      self.canvas.clear();
+      state.request_frame();
+      self.canvas.copy(&background, None, None)?;
 
-      for i in 0..state.game_size.load(Relaxed) {
-        let i = i as u32;
-        unsafe {
-          let unit = *state.game_data.load(Relaxed).offset(i as isize);
-
-          match unit.concept {
-            Concept::Sunflower => {
-              self.canvas.copy(
-                &textures.sunflower,
-                None,
-                Rect::new(
-                  ((i % self.world_width) * self.square_size) as i32,
-                  ((i / self.world_width) * self.square_size) as i32,
-                  self.square_size,
-                  self.square_size,
-                ),
-              )?;
-            }
-            Concept::Rose => {
-              self.canvas.copy(
-                &textures.rose,
-                None,
-                Rect::new(
-                  ((i % self.world_width) * self.square_size) as i32,
-                  ((i / self.world_width) * self.square_size) as i32,
-                  self.square_size,
-                  self.square_size,
-                ),
-              )?;
-            }
-            Concept::Dogwood => {
-              if unit.blood < -35 {
-                self.canvas.copy(
-                  &textures.dogwood,
-                  None,
-                  Rect::new(
-                    ((i % self.world_width) * self.square_size) as i32,
-                    ((i / self.world_width) * self.square_size) as i32,
-                    self.square_size,
-                    self.square_size,
-                  ),
-                )?;
-              } else if unit.blood < -20 {
-                self.canvas.copy(
-                  &textures.dogwood_faded,
-                  None,
-                  Rect::new(
-                    ((i % self.world_width) * self.square_size) as i32,
-                    ((i / self.world_width) * self.square_size) as i32,
-                    self.square_size,
-                    self.square_size,
-                  ),
-                )?;
-              } else if unit.blood < -5 {
-                self.canvas.copy(
-                  &textures.dogwood_relic,
-                  None,
-                  Rect::new(
-                    ((i % self.world_width) * self.square_size) as i32,
-                    ((i / self.world_width) * self.square_size) as i32,
-                    self.square_size,
-                    self.square_size,
-                  ),
-                )?;
-              } else {
-                self.canvas.copy(
-                  &textures.dogwood_ruin,
-                  None,
-                  Rect::new(
-                    ((i % self.world_width) * self.square_size) as i32,
-                    ((i / self.world_width) * self.square_size) as i32,
-                    self.square_size,
-                    self.square_size,
-                  ),
-                )?;
-              }
-            }
-            Concept::Elder => {
-              self.canvas.copy(
-                &textures.elder,
-                None,
-                Rect::new(
-                  ((i % self.world_width) * self.square_size) as i32,
-                  ((i / self.world_width) * self.square_size) as i32,
-                  self.square_size,
-                  self.square_size,
-                ),
-              )?;
-            }
-            Concept::Thistle => {
-              self.canvas.copy(
-                &textures.elder,
-                None,
-                Rect::new(
-                  ((i % self.world_width) * self.square_size) as i32,
-                  ((i / self.world_width) * self.square_size) as i32,
-                  self.square_size,
-                  self.square_size,
-                ),
-              )?;
-            }
-            _ => {}
+      let frame = state.frame();
+      for (i, unit) in frame.cells().enumerate() {
+        let cell_rect = cell_rect(i as u32, self.world_width, self.square_size, self.topology);
+        match unit.concept {
+          Concept::Sunflower => {
+            self.canvas.copy(&textures.sunflower, None, cell_rect)?;
+          }
+          Concept::Rose => {
+            self.canvas.copy(&textures.rose, None, cell_rect)?;
+          }
+          Concept::Dogwood => {
+            let band = [
+              &textures.dogwood,
+              &textures.dogwood_faded,
+              &textures.dogwood_relic,
+              &textures.dogwood_ruin,
+            ][dogwood_band(unit.blood)];
+            self.canvas.copy(band, None, cell_rect)?;
           }
+          Concept::Elder => {
+            self.canvas.copy(&textures.elder, None, cell_rect)?;
+          }
+          Concept::Thistle => {
+            self.canvas.copy(&textures.elder, None, cell_rect)?;
+          }
+          _ => {}
         }
       }
       if self.show_fps {
-        let surface = font
-          .render(&format!("{:03}", frame_rate))
-          .blended(FPS_COLOR)
-          .map_err(|e| e.to_string())?;
-        let texture = self
-          .texture_creator
-          .create_texture_from_surface(&surface)
-          .map_err(|e| e.to_string())?;
-        self
-          .canvas
-          .copy(&texture, None, Some(Rect::new(0, 0, 42, 14)))?;
+        Graphics::draw_text(
+          &mut self.canvas,
+          &self.texture_creator,
+          &font,
+          &format!("{:03}", frame_rate),
+          FPS_COLOR,
+          0,
+        )?;
+      }
+      if let Some(season) = state.season() {
+        Graphics::draw_text(
+          &mut self.canvas,
+          &self.texture_creator,
+          &font,
+          &season.name,
+          SEASON_COLOR,
+          14,
+        )?;
+      }
+      if let Some((stagnation, since)) = state.stagnation() {
+        Graphics::draw_text(
+          &mut self.canvas,
+          &self.texture_creator,
+          &font,
+          &format!("{} since {}", stagnation, since),
+          STAGNATION_COLOR,
+          28,
+        )?;
+      }
+      // Watched cells are outlined and their latest steps listed.
+      let mut line = 42;
+      for (x, y, steps) in state.watched(TRACE_STEPS) {
+        self.canvas.set_draw_color(WATCH_COLOR);
+        self.canvas.draw_rect(cell_rect(
+          x + y * self.world_width,
+          self.world_width,
+          self.square_size,
+          self.topology,
+        ))?;
+        Graphics::draw_text(
+          &mut self.canvas,
+          &self.texture_creator,
+          &font,
+          &format!("({}, {})", x, y),
+          WATCH_COLOR,
+          line,
+        )?;
+        line += 14;
+        for step in steps {
+          let cause = if step.mutated {
+            "mutated".to_string()
+          } else {
+            step.branches(&state.branches).join(", ")
+          };
+          let text = format!(
+            "  {} {} blood {} joy {} {}",
+            step.tick,
+            step.cell.concept.name(),
+            step.cell.blood,
+            step.cell.joy,
+            cause
+          );
+          Graphics::draw_text(
+            &mut self.canvas,
+            &self.texture_creator,
+            &font,
+            &text,
+            WATCH_COLOR,
+            line,
+          )?;
+          line += 14;
+        }
       }
       self.canvas.present();
     }
@@ -2,22 +2,58 @@
 #[macro_use]
 extern crate rocket;
 
+mod access;
+mod admin;
 mod args;
+mod bench;
+mod clusters;
+mod density;
 mod game;
 mod graphics;
+mod picture;
+mod rules;
+mod scenario;
+mod seasons;
+mod stagnation;
+mod terrain;
+mod tiles;
+mod trace;
+mod wire;
 
+use crate::access::{Access, Role};
+use crate::admin::{Command, RunnerStatus};
 use crate::args::parse_args;
+use crate::bench::Seeding;
+use crate::clusters::Cluster;
+use crate::density::{Density, Summary};
 use crate::game::CellState;
 use crate::game::Concept;
+use crate::game::Frame;
 use crate::game::GameState;
+use crate::game::Garden;
 use crate::game::Mutation;
-use std::sync::Mutex;
+use crate::game::Mutations;
+use crate::game::MAX_MUTATIONS;
+use crate::picture::{Overlay, Picture};
+use crate::rules::RuleSet;
+use crate::trace::Trace;
+use crate::wire::{Compression, Content, Packing};
 
+use rocket::data::{Data, ToByteUnit};
 use rocket::fs::NamedFile;
+use rocket::response::status::BadRequest;
+use rocket::response::status::Custom;
+use rocket::response::status::NotFound;
+use rocket::tokio::sync::broadcast::error::TryRecvError;
+use rocket::tokio::sync::oneshot;
+use rocket::tokio::time::{timeout_at, Instant};
 use rocket::State;
 
 use rocket::fairing::{Fairing, Info, Kind};
-use rocket::http::Header;
+use rocket::http::{ContentType, Header, Status as HttpStatus};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::stream::{Event as ServerEvent, EventStream};
+use rocket::response::Responder;
 use rocket::serde::{json::Json, Deserialize, Serialize};
 use rocket::{Request, Response};
 use sdl2::event::Event;
@@ -28,6 +64,72 @@ use std::sync::Arc;
 use std::time::Duration;
 use std::time::SystemTime;
 
+/// Let the request through if its bearer token grants `needed`, or if
+/// anyone is granted it.
+fn authorize(request: &Request<'_>, needed: Role) -> Outcome<(), &'static str> {
+  let access = request
+    .rocket()
+    .state::<Access>()
+    .expect("access is managed");
+  if !access.grants(needed) {
+    return Outcome::Error((HttpStatus::Forbidden, "No token allows this"));
+  }
+  // Pages streaming with `EventSource` cannot set headers, so the token may
+  // come as a query parameter instead.
+  let token = request
+    .headers()
+    .get_one("Authorization")
+    .and_then(|value| value.strip_prefix("Bearer "))
+    .or_else(|| {
+      request
+        .query_value::<&str>("access_token")
+        .and_then(Result::ok)
+    });
+  match access.role(token) {
+    Some(role) if role >= needed => Outcome::Success(()),
+    Some(_) => Outcome::Error((HttpStatus::Forbidden, "The token does not allow this")),
+    None => Outcome::Error((HttpStatus::Unauthorized, "A bearer token is needed")),
+  }
+}
+
+/// Admits viewers and above.
+struct Viewer;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Viewer {
+  type Error = &'static str;
+
+  async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+    authorize(request, Role::Viewer).map(|_| Viewer)
+  }
+}
+
+/// Admits gardeners and admins.
+struct Gardener;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Gardener {
+  type Error = &'static str;
+
+  async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+    authorize(request, Role::Gardener).map(|_| Gardener)
+  }
+}
+
+/// Admits admins.
+struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+  type Error = &'static str;
+
+  async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+    authorize(request, Role::Admin).map(|_| Admin)
+  }
+}
+
+/// Lets pages call the garden: those of the origins in the access file with
+/// credentials, or any without when it lists none.
 pub struct CORS;
 
 #[rocket::async_trait]
@@ -40,32 +142,788 @@ impl Fairing for CORS {
   }
 
   async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
-    response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
+    let access = request
+      .rocket()
+      .state::<Access>()
+      .expect("access is managed");
+    if access.origins.is_empty() {
+      response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
+    } else {
+      response.adjoin_raw_header("Vary", "Origin");
+      match request.headers().get_one("Origin") {
+        Some(origin) if access.allows_origin(origin) => {
+          response.set_header(Header::new(
+            "Access-Control-Allow-Origin",
+            origin.to_string(),
+          ));
+          response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+        }
+        _ => return,
+      }
+    }
     response.set_header(Header::new(
       "Access-Control-Allow-Methods",
-      "POST, GET, PATCH, OPTIONS",
+      "POST, GET, PUT, PATCH, DELETE, OPTIONS",
+    ));
+    response.set_header(Header::new(
+      "Access-Control-Allow-Headers",
+      "Authorization, Content-Type, If-None-Match",
+    ));
+    response.set_header(Header::new(
+      "Access-Control-Expose-Headers",
+      "X-Cell-Layout, ETag, X-Garden-Tick, X-Density-Block, X-Density-Width",
     ));
-    response.set_header(Header::new("Access-Control-Allow-Headers", "*"));
-    response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
   }
 }
 
+/// Answers CORS preflight requests, the fairing adding the headers.
+#[options("/<_..>")]
+fn preflight() {}
+
 #[get("/")]
 async fn index() -> Option<NamedFile> {
   NamedFile::open("./target/index.html").await.ok()
 }
 
-#[get("/garden")]
-fn garden(state: &State<Arc<GameState>>) -> &[u8] {
-  let game = state.inner();
+/// How long `?after=<tick>` holds a request at most.
+const LONG_POLL: Duration = Duration::from_secs(30);
+
+/// The `If-None-Match` header of a request, if any.
+struct IfNoneMatch(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+  type Error = std::convert::Infallible;
+
+  async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+    let value = request.headers().get_one("If-None-Match");
+    Outcome::Success(IfNoneMatch(value.map(str::to_string)))
+  }
+}
+
+impl IfNoneMatch {
+  fn matches(&self, etag: &str) -> bool {
+    self.0.as_ref().is_some_and(|value| {
+      value
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+    })
+  }
+}
+
+/// Sent instead of a frame the client already has.
+#[derive(Responder)]
+#[response(status = 304)]
+struct NotModified {
+  body: (),
+  etag: Header<'static>,
+  tick: Header<'static>,
+}
+
+/// The `ETag` and `X-Garden-Tick` headers of a frame.
+fn frame_headers(frame: &Frame) -> (Header<'static>, Header<'static>) {
+  (
+    Header::new("ETag", frame.etag()),
+    Header::new("X-Garden-Tick", frame.tick.to_string()),
+  )
+}
+
+/// The latest frame, unless the client already has it. Given `after`, waits
+/// up to `LONG_POLL` for a frame of a later tick, and answers not modified
+/// if none came.
+async fn fresh_frame(
+  game: &GameState,
+  after: Option<u64>,
+  if_none_match: &IfNoneMatch,
+) -> Result<Arc<Frame>, NotModified> {
+  game.request_frame();
+  let mut frame = game.frame();
+  if let Some(after) = after {
+    let deadline = Instant::now() + LONG_POLL;
+    while frame.tick <= after {
+      let next_frame = game.next_frame();
+      // Read again once waiting, so a frame published meanwhile is not missed.
+      frame = game.frame();
+      if frame.tick > after {
+        break;
+      }
+      game.request_frame();
+      if timeout_at(deadline, next_frame).await.is_err() {
+        break;
+      }
+      frame = game.frame();
+    }
+  }
+
+  if after.is_some_and(|after| frame.tick <= after) || if_none_match.matches(&frame.etag()) {
+    let (etag, tick) = frame_headers(&frame);
+    return Err(NotModified {
+      body: (),
+      etag,
+      tick,
+    });
+  }
+  Ok(frame)
+}
+
+/// Raw cells of a frame, with `X-Cell-Layout` naming how they are laid out.
+#[derive(Responder)]
+#[response(content_type = "binary")]
+struct Cells {
+  bytes: Vec<u8>,
+  layout: Header<'static>,
+  etag: Header<'static>,
+  tick: Header<'static>,
+}
+
+/// The latest frame's cells as stored. Honours `If-None-Match`, and with
+/// `after` waits for a frame past that tick.
+#[get("/garden?<after>")]
+async fn garden(
+  _viewer: Viewer,
+  after: Option<u64>,
+  if_none_match: IfNoneMatch,
+  state: &State<Garden>,
+) -> Result<Cells, NotModified> {
+  let frame = fresh_frame(&state.current(), after, &if_none_match).await?;
+  let (etag, tick) = frame_headers(&frame);
+  Ok(Cells {
+    bytes: frame.as_bytes().to_vec(),
+    layout: Header::new("X-Cell-Layout", frame.layout.name()),
+    etag,
+    tick,
+  })
+}
+
+/// One cell of the latest frame. Not found outside the world.
+#[get("/cell/<x>/<y>")]
+fn cell(
+  _viewer: Viewer,
+  x: u32,
+  y: u32,
+  state: &State<Garden>,
+) -> Result<Json<CellState>, NotFound<String>> {
+  let game = state.current();
+  game.request_frame();
+
+  if x >= game.world_width || y >= game.world_width {
+    return Err(NotFound(format!("({}, {}) is outside the world", x, y)));
+  }
+  let cell = game.frame().cell((x + y * game.world_width) as usize);
+  Ok(Json(cell.expect("frames cover the world")))
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Region {
+  /// Tick of the frame the cells were read from.
+  tick: u64,
+  x: u32,
+  y: u32,
+  w: u32,
+  h: u32,
+  /// Row by row.
+  cells: Vec<CellState>,
+}
+
+/// The `w` by `h` cells from (`x`, `y`) of the latest frame. The region must
+/// lie within the world.
+#[get("/garden/region?<x>&<y>&<w>&<h>")]
+fn region(
+  _viewer: Viewer,
+  x: Option<u32>,
+  y: Option<u32>,
+  w: Option<u32>,
+  h: Option<u32>,
+  state: &State<Garden>,
+) -> Result<Json<Region>, BadRequest<String>> {
+  let game = state.current();
+  game.request_frame();
+
+  let (x, y, w, h) = match (x, y, w, h) {
+    (Some(x), Some(y), Some(w), Some(h)) => (x, y, w, h),
+    _ => return Err(BadRequest("x, y, w and h are all needed".to_string())),
+  };
+  check_region(x, y, w, h, game.world_width)?;
+
+  let frame = game.frame();
+  Ok(Json(Region {
+    tick: frame.tick,
+    x,
+    y,
+    w,
+    h,
+    cells: frame.region(game.world_width, x, y, w, h),
+  }))
+}
+
+fn check_region(x: u32, y: u32, w: u32, h: u32, width: u32) -> Result<(), BadRequest<String>> {
+  if w == 0 || h == 0 || x >= width || y >= width || w > width - x || h > width - y {
+    return Err(BadRequest(format!(
+      "A {}x{} region at ({}, {}) does not fit a world {} cells wide",
+      w, h, x, y, width
+    )));
+  }
+  Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Change {
+  x: u32,
+  y: u32,
+  #[serde(flatten)]
+  cell: CellState,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Delta {
+  tick: u64,
+  /// Cells of the viewport that differ from the previous message's.
+  changes: Vec<Change>,
+}
+
+/// Server-sent events following the garden within a viewport, the whole
+/// world by default. A `keyframe` event holds a `Region` of the viewport,
+/// sent on connect and every `keyframe` messages after, 100 by default.
+/// Every frame in between sends a `delta` event. Frames are published at
+/// most at the readback rate, so a delta covers every tick since the last.
+/// Each change in how the garden stagnates sends a `stagnation` event, see
+/// `stagnation::Change`, before the frame it was noticed by.
+/// A resize restarts the stream with a keyframe of the new world, or ends it
+/// if the viewport no longer fits.
+#[get("/garden/stream?<x>&<y>&<w>&<h>&<keyframe>")]
+fn stream(
+  _viewer: Viewer,
+  x: Option<u32>,
+  y: Option<u32>,
+  w: Option<u32>,
+  h: Option<u32>,
+  keyframe: Option<u32>,
+  state: &State<Garden>,
+) -> Result<EventStream![ServerEvent + '_], BadRequest<String>> {
+  let (x, y) = (x.unwrap_or(0), y.unwrap_or(0));
+  let viewport = move |world_width: u32| {
+    let w = w.unwrap_or_else(|| world_width.saturating_sub(x));
+    let h = h.unwrap_or_else(|| world_width.saturating_sub(y));
+    check_region(x, y, w, h, world_width).map(|_| (w, h))
+  };
+  let mut game = state.current();
+  let (mut w, mut h) = viewport(game.world_width)?;
+  let mut changes = game.stagnation_changes();
+  let keyframe_every = keyframe.unwrap_or(100).max(1);
+
+  Ok(EventStream! {
+    let mut previous: Vec<CellState> = Vec::new();
+    let mut since_keyframe = 0;
+    loop {
+      let current = state.current();
+      if !Arc::ptr_eq(&current, &game) {
+        game = current;
+        match viewport(game.world_width) {
+          Ok(size) => (w, h) = size,
+          Err(_) => break,
+        }
+        changes = game.stagnation_changes();
+        previous.clear();
+      }
+      loop {
+        match changes.try_recv() {
+          Ok(change) => yield ServerEvent::json(&change).event("stagnation"),
+          Err(TryRecvError::Lagged(_)) => continue,
+          Err(_) => break,
+        }
+      }
+      game.request_frame();
+      let next_frame = game.next_frame();
+      let frame = game.frame();
+      let cells = frame.region(game.world_width, x, y, w, h);
+      if previous.is_empty() || since_keyframe == keyframe_every {
+        since_keyframe = 0;
+        yield ServerEvent::json(&Region {
+          tick: frame.tick,
+          x,
+          y,
+          w,
+          h,
+          cells: cells.clone(),
+        })
+        .event("keyframe");
+      } else {
+        let changes = cells
+          .iter()
+          .zip(&previous)
+          .enumerate()
+          .filter(|(_, (cell, before))| cell != before)
+          .map(|(i, (cell, _))| Change {
+            x: x + i as u32 % w,
+            y: y + i as u32 / w,
+            cell: *cell,
+          })
+          .collect();
+        yield ServerEvent::json(&Delta {
+          tick: frame.tick,
+          changes,
+        })
+        .event("delta");
+      }
+      since_keyframe += 1;
+      previous = cells;
+      // A resize after this check wakes `next_frame`, see `Garden::replace`.
+      if Arc::ptr_eq(&state.current(), &game) {
+        next_frame.await;
+      }
+    }
+  })
+}
+
+/// The compression a request accepts, see `Compression::accepted`.
+struct AcceptEncoding(Option<Compression>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AcceptEncoding {
+  type Error = std::convert::Infallible;
+
+  async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+    let accepted = request
+      .headers()
+      .get("Accept-Encoding")
+      .find_map(Compression::accepted);
+    Outcome::Success(AcceptEncoding(accepted))
+  }
+}
+
+/// A frame in the format of `wire.rs`, compressed if the client accepts it.
+struct Framed {
+  bytes: Vec<u8>,
+  compression: Option<Compression>,
+  headers: (Header<'static>, Header<'static>),
+}
+
+impl<'r> Responder<'r, 'static> for Framed {
+  fn respond_to(self, _: &'r Request<'_>) -> rocket::response::Result<'static> {
+    let mut response = Response::build();
+    response
+      .header(ContentType::new("application", "vnd.bit-garden"))
+      .header(self.headers.0)
+      .header(self.headers.1)
+      .raw_header("Vary", "Accept-Encoding");
+    let bytes = match self.compression {
+      Some(compression) => {
+        response.raw_header("Content-Encoding", compression.name());
+        compression.compress(&self.bytes)
+      }
+      None => self.bytes,
+    };
+    response
+      .sized_body(bytes.len(), std::io::Cursor::new(bytes))
+      .ok()
+  }
+}
+
+/// A frame's `ETag` marked weak, for responses whose bytes also depend on the
+/// `Content-Encoding` negotiated.
+fn weak(etag: Header<'static>) -> Header<'static> {
+  Header::new("ETag", format!("W/{}", etag.value()))
+}
+
+/// The latest frame with a header describing it. `content` is `cells`
+/// (default) or `concepts`, `packing` is `none` (default) or `rle`.
+/// Conditional and long-polled like `/garden`, with a weak `ETag` as the
+/// body varies with `Accept-Encoding`.
+#[get("/garden/frame?<content>&<packing>&<after>")]
+async fn framed_garden(
+  _viewer: Viewer,
+  content: Option<&str>,
+  packing: Option<&str>,
+  after: Option<u64>,
+  accept: AcceptEncoding,
+  if_none_match: IfNoneMatch,
+  state: &State<Garden>,
+) -> Result<Result<Framed, NotModified>, BadRequest<String>> {
+  let content = match content {
+    None => Content::Cells,
+    Some(name) => Content::parse(name).ok_or_else(|| {
+      BadRequest(format!(
+        "Unknown content {}, expected cells or concepts",
+        name
+      ))
+    })?,
+  };
+  let packing = match packing {
+    None => Packing::None,
+    Some(name) => Packing::parse(name)
+      .ok_or_else(|| BadRequest(format!("Unknown packing {}, expected none or rle", name)))?,
+  };
+
+  let game = state.current();
+  let frame = match fresh_frame(&game, after, &if_none_match).await {
+    Ok(frame) => frame,
+    Err(not_modified) => {
+      return Ok(Err(NotModified {
+        etag: weak(not_modified.etag),
+        ..not_modified
+      }))
+    }
+  };
+  let (etag, tick) = frame_headers(&frame);
+  Ok(Ok(Framed {
+    bytes: wire::encode(&frame, game.world_width, content, packing),
+    compression: accept.0,
+    headers: (weak(etag), tick),
+  }))
+}
+
+/// Largest number of pixels a side a cell of `/garden.png` can take.
+const MAX_SCALE: u32 = 32;
+
+#[derive(Responder)]
+#[response(content_type = "image/png")]
+struct Png {
+  bytes: Vec<u8>,
+  etag: Header<'static>,
+  tick: Header<'static>,
+}
+
+/// The latest frame as a PNG, `scale` pixels a side per cell (default 1).
+/// `x`, `y`, `w` and `h` crop it to a region, `overlay` colours cells by
+/// `concept` (default) as the window does, or by `blood` or `joy` heat.
+/// Conditional and long-polled like `/garden`.
+#[allow(clippy::too_many_arguments)]
+#[get("/garden.png?<scale>&<x>&<y>&<w>&<h>&<overlay>&<after>")]
+async fn garden_png(
+  _viewer: Viewer,
+  scale: Option<u32>,
+  x: Option<u32>,
+  y: Option<u32>,
+  w: Option<u32>,
+  h: Option<u32>,
+  overlay: Option<&str>,
+  after: Option<u64>,
+  if_none_match: IfNoneMatch,
+  state: &State<Garden>,
+) -> Result<Result<Png, NotModified>, BadRequest<String>> {
+  let game = state.current();
+  let overlay = match overlay {
+    None => Overlay::Concept,
+    Some(name) => Overlay::parse(name).ok_or_else(|| {
+      BadRequest(format!(
+        "Unknown overlay {}, expected concept, blood or joy",
+        name
+      ))
+    })?,
+  };
+  let scale = scale.unwrap_or(1);
+  if scale == 0 || scale > MAX_SCALE {
+    return Err(BadRequest(format!(
+      "Scale must be between 1 and {}",
+      MAX_SCALE
+    )));
+  }
+  let crop = match (x, y, w, h) {
+    (None, None, None, None) => (0, 0, game.world_width, game.world_width),
+    (Some(x), Some(y), Some(w), Some(h)) => (x, y, w, h),
+    _ => {
+      return Err(BadRequest(
+        "Crop with all of x, y, w and h or none".to_string(),
+      ))
+    }
+  };
+  check_region(crop.0, crop.1, crop.2, crop.3, game.world_width)?;
+  if crop.2.max(crop.3) * scale > picture::MAX_SIDE {
+    return Err(BadRequest(format!(
+      "Pictures are at most {} pixels a side",
+      picture::MAX_SIDE
+    )));
+  }
+
+  let frame = match fresh_frame(&game, after, &if_none_match).await {
+    Ok(frame) => frame,
+    Err(not_modified) => return Ok(Err(not_modified)),
+  };
+  let picture = Picture::render(
+    &frame,
+    &game.terrain,
+    game.world_width,
+    game.topology,
+    crop,
+    scale,
+    overlay,
+  );
+  let (etag, tick) = frame_headers(&frame);
+  Ok(Ok(Png {
+    bytes: picture.png(),
+    etag,
+    tick,
+  }))
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Pyramid {
+  tile_size: u32,
+  /// Zoom at which a pixel is a cell.
+  native_zoom: u32,
+  max_zoom: u32,
+}
+
+/// How the tiles of `/tiles/<z>/<x>/<y>.png` are laid out.
+#[get("/tiles")]
+fn pyramid(_viewer: Viewer, state: &State<Garden>) -> Json<Pyramid> {
+  let native_zoom = tiles::native_zoom(state.current().world_width);
+  Json(Pyramid {
+    tile_size: tiles::TILE_SIZE,
+    native_zoom,
+    max_zoom: native_zoom + tiles::OVERZOOM,
+  })
+}
+
+/// Tile (`x`, `y`) of zoom `z` of the pyramid described in `tiles.rs`, as
+/// a PNG. Honours `If-None-Match` like `/garden`.
+#[get("/tiles/<z>/<x>/<file>")]
+fn tile(
+  _viewer: Viewer,
+  z: u32,
+  x: u32,
+  file: &str,
+  if_none_match: IfNoneMatch,
+  state: &State<Garden>,
+) -> Result<Result<Png, NotModified>, NotFound<String>> {
+  let game = state.current();
+  let y = file
+    .strip_suffix(".png")
+    .and_then(|y| y.parse::<u32>().ok())
+    .ok_or_else(|| NotFound(format!("No tile {}, expected <y>.png", file)))?;
+  let max_zoom = tiles::native_zoom(game.world_width) + tiles::OVERZOOM;
+  if z > max_zoom {
+    return Err(NotFound(format!("Zoom goes from 0 to {}", max_zoom)));
+  }
+  if x >= 1 << z || y >= 1 << z {
+    return Err(NotFound(format!(
+      "Zoom {} has {} tiles a side",
+      z,
+      1u32 << z
+    )));
+  }
+
+  game.request_frame();
+  let frame = game.frame();
+  if if_none_match.matches(&frame.etag()) {
+    let (etag, tick) = frame_headers(&frame);
+    return Ok(Err(NotModified {
+      body: (),
+      etag,
+      tick,
+    }));
+  }
+  let (frame, level) = game.level(tiles::block_size(game.world_width, z));
+  let (etag, tick) = frame_headers(&frame);
+  Ok(Ok(Png {
+    bytes: level.tile(game.world_width, z, x, y).png(),
+    etag,
+    tick,
+  }))
+}
+
+/// How long `/garden/density` waits for a tick to be summarized.
+const DENSITY_WAIT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct DensityGrid {
+  /// Ticks run before the summarized one.
+  tick: u64,
+  block: u32,
+  /// Blocks a side.
+  width: u32,
+  /// Names of the concepts, in the order of each block's counts.
+  concepts: Vec<&'static str>,
+  /// Row by row.
+  blocks: Vec<Summary>,
+}
+
+/// Density summaries in the binary form of `density.rs`.
+#[derive(Responder)]
+#[response(content_type = "binary")]
+struct DensityBytes {
+  bytes: Vec<u8>,
+  tick: Header<'static>,
+  block: Header<'static>,
+  width: Header<'static>,
+}
+
+#[derive(Responder)]
+enum DensityResponse {
+  Json(Json<DensityGrid>),
+  Binary(DensityBytes),
+}
+
+/// The count of each concept and the mean blood and joy of every `block` by
+/// `block` cells (default 16) of the next tick, reduced on the GPU. `format`
+/// is `json` (default) or `binary`. Summaries of an earlier tick are served
+/// if none come in time, when there are some of that block size.
+#[get("/garden/density?<block>&<format>")]
+async fn density_grid(
+  _viewer: Viewer,
+  block: Option<u32>,
+  format: Option<&str>,
+  state: &State<Garden>,
+) -> Result<DensityResponse, Custom<String>> {
+  let game = state.current();
+  let block = block.unwrap_or(density::MIN_BLOCK);
+  let largest = density::MAX_BLOCK.min(game.world_width);
+  if block < density::MIN_BLOCK || block > largest {
+    return Err(Custom(
+      HttpStatus::BadRequest,
+      format!(
+        "Blocks must be between {} and {} cells a side",
+        density::MIN_BLOCK,
+        largest
+      ),
+    ));
+  }
+  let binary = match format {
+    None | Some("json") => false,
+    Some("binary") => true,
+    Some(name) => {
+      return Err(Custom(
+        HttpStatus::BadRequest,
+        format!("Unknown format {}, expected json or binary", name),
+      ))
+    }
+  };
 
-  unsafe {
-    return std::slice::from_raw_parts(
-      game.game_data.load(Relaxed) as *const u8,
-      game.game_size.load(Relaxed) as usize
-        * (std::mem::size_of::<CellState>() / std::mem::size_of::<u8>()),
-    );
+  let sized = |density: Option<Arc<Density>>| density.filter(|d| d.block == block);
+  let deadline = Instant::now() + DENSITY_WAIT;
+  let density = loop {
+    let next_density = game.next_density();
+    // Asked again every time, as another size may have been asked for since.
+    game.request_density(block);
+    if timeout_at(deadline, next_density).await.is_err() {
+      break sized(game.density()).ok_or_else(|| {
+        Custom(
+          HttpStatus::ServiceUnavailable,
+          "No tick was summarized in time".to_string(),
+        )
+      })?;
+    }
+    if let Some(density) = sized(game.density()) {
+      break density;
+    }
   };
+
+  Ok(if binary {
+    DensityResponse::Binary(DensityBytes {
+      bytes: density.to_bytes(),
+      tick: Header::new("X-Garden-Tick", density.tick.to_string()),
+      block: Header::new("X-Density-Block", density.block.to_string()),
+      width: Header::new("X-Density-Width", density.width.to_string()),
+    })
+  } else {
+    DensityResponse::Json(Json(DensityGrid {
+      tick: density.tick,
+      block: density.block,
+      width: density.width,
+      concepts: Concept::ALL.iter().map(|c| c.name()).collect(),
+      blocks: density.blocks.clone(),
+    }))
+  })
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Status {
+  tick: u64,
+  /// Tick of the latest frame served by `/garden`, which may lag behind.
+  frame_tick: u64,
+  season: Option<String>,
+  /// `extinct`, `static` or `cycling every <period> ticks`, once the garden
+  /// stops changing.
+  stagnation: Option<String>,
+  /// Tick the garden was first seen stagnant.
+  stagnant_since: Option<u64>,
+}
+
+#[get("/status")]
+fn status(_viewer: Viewer, state: &State<Garden>) -> Json<Status> {
+  let game = state.current();
+  let stagnation = game.stagnation();
+
+  Json(Status {
+    tick: game.tick.load(Relaxed),
+    frame_tick: game.frame().tick,
+    season: game.season().map(|season| season.name.clone()),
+    stagnation: stagnation.map(|(stagnation, _)| stagnation.to_string()),
+    stagnant_since: stagnation.map(|(_, since)| since),
+  })
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ConceptClusters {
+  concept: &'static str,
+  clusters: usize,
+  cells: usize,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ClusterSummary {
+  /// Tick of the frame the clusters were found in.
+  tick: u64,
+  concepts: Vec<ConceptClusters>,
+  largest: Vec<Cluster>,
+}
+
+/// Number of clusters of each concept and the `top` largest, 10 by default.
+#[get("/clusters?<top>")]
+fn cluster_summary(
+  _viewer: Viewer,
+  top: Option<usize>,
+  state: &State<Garden>,
+) -> Json<ClusterSummary> {
+  let game = state.current();
+  game.request_frame();
+
+  let (frame, clusters) = game.clusters();
+  let concepts = Concept::ALL[1..]
+    .iter()
+    .map(|concept| {
+      let of_concept = clusters.iter().filter(|c| c.concept == concept.name());
+      ConceptClusters {
+        concept: concept.name(),
+        clusters: of_concept.clone().count(),
+        cells: of_concept.map(|c| c.size).sum(),
+      }
+    })
+    .collect();
+  Json(ClusterSummary {
+    tick: frame.tick,
+    concepts,
+    largest: clusters.iter().take(top.unwrap_or(10)).cloned().collect(),
+  })
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ClusterList {
+  tick: u64,
+  clusters: Vec<Cluster>,
+}
+
+/// Every cluster of the latest frame, largest first.
+#[get("/clusters/all")]
+fn all_clusters(_viewer: Viewer, state: &State<Garden>) -> Json<ClusterList> {
+  let game = state.current();
+  game.request_frame();
+
+  let (frame, clusters) = game.clusters();
+  Json(ClusterList {
+    tick: frame.tick,
+    clusters: clusters.to_vec(),
+  })
 }
 
 #[derive(Serialize, Deserialize)]
@@ -74,29 +932,355 @@ struct Position {
   x: u32,
   y: u32,
 }
-#[post("/mutate", format = "application/json", data = "<pos>")]
-fn mutate(pos: Json<Position>, state: &State<Arc<Mutex<Vec<Mutation>>>>) {
+
+/// A concept by name, or by its number in the shaders.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde", untagged)]
+enum ConceptRef {
+  Id(usize),
+  Name(String),
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct MutationRequest {
+  x: u32,
+  y: u32,
+  /// A sunflower when left out.
+  concept: Option<ConceptRef>,
+  /// Kept from the cell when left out.
+  blood: Option<i32>,
+  joy: Option<i32>,
+}
+
+impl MutationRequest {
+  fn mutation(&self, world_width: u32) -> Result<Mutation, String> {
+    if self.x >= world_width || self.y >= world_width {
+      return Err(format!(
+        "({}, {}) is outside the {}x{} world",
+        self.x, self.y, world_width, world_width
+      ));
+    }
+    let concept = match &self.concept {
+      None => Concept::Sunflower,
+      Some(ConceptRef::Id(id)) => *Concept::ALL
+        .get(*id)
+        .ok_or_else(|| format!("Unknown concept {}", id))?,
+      Some(ConceptRef::Name(name)) => {
+        Concept::named(name).ok_or_else(|| format!("Unknown concept {}", name))?
+      }
+    };
+    Ok(Mutation {
+      blood: self.blood,
+      joy: self.joy,
+      ..Mutation::plant(self.x, self.y, concept)
+    })
+  }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde", untagged)]
+enum MutationBody {
+  One(MutationRequest),
+  Many(Vec<MutationRequest>),
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ApiError {
+  error: String,
+}
+
+fn api_error(error: String) -> BadRequest<Json<ApiError>> {
+  BadRequest(Json(ApiError { error }))
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Queued {
+  /// Ticks run when the mutations were queued; they land in a later one.
+  tick: u64,
+  queued: usize,
+}
+
+/// Queue one mutation or an array of at most `MAX_MUTATIONS`, applied
+/// together in one tick after those queued before. Within the batch they
+/// apply in order, so where several change one cell the last one's fields
+/// win. None are queued if any is invalid.
+#[post("/mutate", format = "application/json", data = "<body>")]
+fn mutate(
+  _gardener: Gardener,
+  body: Result<Json<MutationBody>, rocket::serde::json::Error<'_>>,
+  garden: &State<Garden>,
+  state: &State<Mutations>,
+) -> Result<Json<Queued>, BadRequest<Json<ApiError>>> {
+  let game = garden.current();
+  let requests = match body.map_err(|e| api_error(e.to_string()))?.into_inner() {
+    MutationBody::One(request) => vec![request],
+    MutationBody::Many(requests) => requests,
+  };
+  if requests.len() > MAX_MUTATIONS {
+    return Err(api_error(format!(
+      "At most {} mutations can be queued at once",
+      MAX_MUTATIONS
+    )));
+  }
+  let batch = requests
+    .iter()
+    .enumerate()
+    .map(|(i, request)| {
+      request
+        .mutation(game.world_width)
+        .map_err(|e| format!("Mutation {}: {}", i, e))
+    })
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(api_error)?;
+
   let mut mutations = state.inner().lock().unwrap();
+  let queued = batch.len();
+  mutations.push_back(batch);
+  Ok(Json(Queued {
+    tick: game.tick.load(Relaxed),
+    queued,
+  }))
+}
 
-  mutations.insert(
-    0,
-    Mutation {
-      x: pos.x,
-      y: pos.y,
-      concept: Concept::Sunflower,
-    },
-  )
+/// The history of every watched cell, oldest first.
+#[get("/watch")]
+fn traces(_viewer: Viewer, state: &State<Garden>) -> Json<Vec<Trace>> {
+  Json(state.current().traces())
+}
+
+/// Start recording the cell at the position every tick.
+#[post("/watch", format = "application/json", data = "<pos>")]
+fn pin(
+  _gardener: Gardener,
+  pos: Json<Position>,
+  state: &State<Garden>,
+) -> Result<(), BadRequest<String>> {
+  state.current().pin(pos.x, pos.y).map_err(BadRequest)
+}
+
+/// Stop watching a cell. Not found if it was not watched.
+#[delete("/watch/<x>/<y>")]
+fn unpin(_gardener: Gardener, x: u32, y: u32, state: &State<Garden>) -> Option<()> {
+  if state.current().unpin(x, y) {
+    Some(())
+  } else {
+    None
+  }
+}
+
+/// Where admin routes send commands for the runner's thread.
+type Commands = std::sync::mpsc::Sender<admin::Request>;
+
+/// Have the runner carry out `command` between ticks. A resize replaces the
+/// garden served.
+async fn command(
+  command: Command,
+  runner: &State<Commands>,
+  garden: &State<Garden>,
+) -> Result<Json<RunnerStatus>, BadRequest<String>> {
+  let resizing = matches!(command, Command::Resize(_));
+  let (reply, replied) = oneshot::channel();
+  runner
+    .send(admin::Request { command, reply })
+    .expect("the runner stopped");
+  let status = replied
+    .await
+    .expect("the runner stopped")
+    .map_err(BadRequest)?;
+  if resizing {
+    garden.replace(status.game.clone());
+  }
+  Ok(Json(status))
+}
+
+#[get("/admin/status")]
+async fn admin_status(
+  _admin: Admin,
+  runner: &State<Commands>,
+  garden: &State<Garden>,
+) -> Result<Json<RunnerStatus>, BadRequest<String>> {
+  command(Command::Status, runner, garden).await
+}
+
+#[post("/admin/pause")]
+async fn pause(
+  _admin: Admin,
+  runner: &State<Commands>,
+  garden: &State<Garden>,
+) -> Result<Json<RunnerStatus>, BadRequest<String>> {
+  command(Command::Pause, runner, garden).await
+}
+
+#[post("/admin/resume")]
+async fn resume(
+  _admin: Admin,
+  runner: &State<Commands>,
+  garden: &State<Garden>,
+) -> Result<Json<RunnerStatus>, BadRequest<String>> {
+  command(Command::Resume, runner, garden).await
+}
+
+/// Run `ticks` ticks (default 1) right away, even while paused.
+#[post("/admin/step?<ticks>")]
+async fn step(
+  ticks: Option<u32>,
+  _admin: Admin,
+  runner: &State<Commands>,
+  garden: &State<Garden>,
+) -> Result<Json<RunnerStatus>, BadRequest<String>> {
+  command(Command::Step(ticks.unwrap_or(1)), runner, garden).await
+}
+
+#[post("/admin/rate?<ticks_per_second>")]
+async fn rate(
+  ticks_per_second: u32,
+  _admin: Admin,
+  runner: &State<Commands>,
+  garden: &State<Garden>,
+) -> Result<Json<RunnerStatus>, BadRequest<String>> {
+  command(Command::Rate(ticks_per_second), runner, garden).await
+}
+
+/// Start over at tick 0 from a world seeded by `generator` (`soil`,
+/// `sunflowers` or `grove`, default `sunflowers`) with `seed`, random if
+/// not given.
+#[post("/admin/reset?<generator>&<seed>")]
+async fn reset(
+  generator: Option<&str>,
+  seed: Option<u64>,
+  _admin: Admin,
+  runner: &State<Commands>,
+  garden: &State<Garden>,
+) -> Result<Json<RunnerStatus>, BadRequest<String>> {
+  let seeding = match generator {
+    None => Seeding::Sunflowers,
+    Some(name) => Seeding::parse(name).ok_or_else(|| {
+      BadRequest(format!(
+        "Unknown generator {}, expected soil, sunflowers or grove",
+        name
+      ))
+    })?,
+  };
+  let seed = seed.unwrap_or_else(rand::random::<u64>);
+  command(Command::Reset { seeding, seed }, runner, garden).await
+}
+
+/// The current cells in the format of `/garden/frame`, run-length encoded,
+/// to load back with `PUT /admin/snapshot`.
+#[get("/admin/snapshot")]
+async fn save_snapshot(
+  _admin: Admin,
+  accept: AcceptEncoding,
+  runner: &State<Commands>,
+  garden: &State<Garden>,
+) -> Result<Framed, BadRequest<String>> {
+  let status = command(Command::Snapshot, runner, garden).await?;
+  let game = &status.game;
+  let frame = game.frame();
+  Ok(Framed {
+    bytes: wire::encode(&frame, game.world_width, Content::Cells, Packing::RunLength),
+    compression: accept.0,
+    headers: frame_headers(&frame),
+  })
+}
+
+/// Replace the cells with a frame of whole cells as `/garden/frame` serves
+/// them, keeping the tick. The world must be as wide as the frame.
+#[put("/admin/snapshot", data = "<data>")]
+async fn load_snapshot(
+  data: Data<'_>,
+  _admin: Admin,
+  runner: &State<Commands>,
+  garden: &State<Garden>,
+) -> Result<Json<RunnerStatus>, BadRequest<String>> {
+  let world_width = garden.current().world_width as u64;
+  // Room for the widest cells, unpacked, and a generous header.
+  let limit = (world_width * world_width * 12 + 4096).bytes();
+  let bytes = data
+    .open(limit)
+    .into_bytes()
+    .await
+    .map_err(|e| BadRequest(e.to_string()))?;
+  if !bytes.is_complete() {
+    return Err(BadRequest("The frame is larger than the world".to_string()));
+  }
+  let cells = wire::decode(&bytes, world_width as u32).map_err(BadRequest)?;
+  command(Command::Load(cells), runner, garden).await
+}
+
+/// Set rule parameters from a JSON object of values by season spec key,
+/// over whatever the seasons say.
+#[patch("/admin/rules", format = "application/json", data = "<rules>")]
+async fn set_rules(
+  rules: Json<std::collections::BTreeMap<String, i32>>,
+  _admin: Admin,
+  runner: &State<Commands>,
+  garden: &State<Garden>,
+) -> Result<Json<RunnerStatus>, BadRequest<String>> {
+  let rules = rules.into_inner().into_iter().collect();
+  command(Command::Rules(rules), runner, garden).await
+}
+
+/// Start a new runner for a world `size` cells wide, seeded afresh. Streams
+/// move on to the new world, long polls waiting on the old one answer not
+/// modified.
+#[post("/admin/resize?<size>")]
+async fn resize(
+  size: u32,
+  _admin: Admin,
+  runner: &State<Commands>,
+  garden: &State<Garden>,
+) -> Result<Json<RunnerStatus>, BadRequest<String>> {
+  command(Command::Resize(size), runner, garden).await
 }
 
 #[launch]
 fn rocket() -> _ {
   let args = parse_args(std::env::args().skip(1).collect());
+  let terrain = Arc::new(
+    terrain::generate(&args.terrain, args.size, args.seed).expect("failed to load terrain"),
+  );
+  let rule_source = match &args.rules {
+    Some(path) => std::fs::read_to_string(path).expect("failed to read rules"),
+    None => rules::GARDEN_RULES.to_string(),
+  };
+  let rules = RuleSet::parse(&rule_source).unwrap_or_else(|e| panic!("Invalid rules: {}", e));
+  if args.bench > 0 {
+    bench::report(&args, &rules, &terrain);
+    std::process::exit(0);
+  }
+  if let Some(path) = &args.scenario {
+    let source = std::fs::read_to_string(path).expect("failed to read scenario");
+    let scenario =
+      scenario::Scenario::parse(&source).unwrap_or_else(|e| panic!("Invalid scenario: {}", e));
+    let passed = scenario.run(&args, &rules);
+    std::process::exit(if passed { 0 } else { 1 });
+  }
+  let shaders = rules
+    .compile(args.topology, args.counting, args.cell_layout)
+    .unwrap_or_else(|e| panic!("Could not compile rules: {}", e));
+  let terrain_for_runner = terrain.clone();
+  let runner_args = args.clone();
 
   let (snd_state, rcv_state) = std::sync::mpsc::channel::<Arc<GameState>>();
-  let (snd_mutations, rcv_mutations) = std::sync::mpsc::channel::<Arc<Mutex<Vec<Mutation>>>>();
+  let (snd_mutations, rcv_mutations) = std::sync::mpsc::channel::<Mutations>();
+  let (snd_commands, rcv_commands) = std::sync::mpsc::channel::<admin::Request>();
 
   std::thread::spawn(move || {
-    let mut runner = game::Runner::new(args.size);
+    let args = runner_args;
+    let mut control = admin::Control::new(args.clone(), shaders);
+    let mut runner = control
+      .runner(args.size, &terrain_for_runner)
+      .unwrap_or_else(|e| panic!("Could not start the runner: {}", e));
+    if args.verify_rules > 0 {
+      runner
+        .verify_rules(&rules, &terrain_for_runner, args.verify_rules)
+        .unwrap_or_else(|e| panic!("{}", e));
+      println!("GPU matches CPU rules for {} ticks", args.verify_rules);
+    }
     snd_state.send(runner.game_state.clone()).unwrap();
     snd_mutations.send(runner.mutations.clone()).unwrap();
 
@@ -104,25 +1288,53 @@ fn rocket() -> _ {
     let mut next_frame: u128 = clock.elapsed().unwrap().as_millis();
 
     loop {
+      // Admin commands run between ticks.
+      while let Ok(request) = rcv_commands.try_recv() {
+        let _ = request
+          .reply
+          .send(control.serve(&mut runner, request.command));
+      }
+
       let curr_time = clock.elapsed().unwrap().as_millis();
       if curr_time >= next_frame {
-        next_frame = curr_time + (1000 / (args.update_rate as u128));
+        next_frame = curr_time + (1000 / (control.tick_rate as u128));
         runner.execute();
       } else {
-        std::thread::sleep(Duration::from_millis((next_frame - curr_time) as u64));
+        // Nothing to submit until the next tick, so let the last ones land,
+        // and take commands while waiting.
+        runner.finish();
+        let wait = Duration::from_millis((next_frame - curr_time) as u64);
+        if let Ok(request) = rcv_commands.recv_timeout(wait) {
+          let _ = request
+            .reply
+            .send(control.serve(&mut runner, request.command));
+        }
       }
     }
   });
 
   let state_ref_for_graphics = rcv_state.recv().unwrap();
   let state_ref_for_web = state_ref_for_graphics.clone();
+  let access = match &args.access {
+    Some(path) => Access::load(path).unwrap_or_else(|e| panic!("Invalid access file: {}", e)),
+    None => Access::open(),
+  };
   let mutations_ref_for_web = rcv_mutations.recv().unwrap();
   let mutations_ref_for_graphics = mutations_ref_for_web.clone();
   if args.show_graphics {
     std::thread::spawn(move || {
-      let mut graphics =
-        graphics::Graphics::new(args.pixel_size, args.size, args.draw_rate, args.show_fps)
-          .expect("failed to load graphics");
+      let state_ref_for_events = state_ref_for_graphics.clone();
+      // Clicking with Ctrl held pins or unpins a cell instead of planting.
+      let mut pinning = false;
+      let mut graphics = graphics::Graphics::new(
+        args.pixel_size,
+        args.size,
+        args.draw_rate,
+        args.show_fps,
+        args.topology,
+        terrain,
+      )
+      .expect("failed to load graphics");
 
       graphics
         .run(state_ref_for_graphics, |event: Event| {
@@ -141,37 +1353,69 @@ fn rocket() -> _ {
             } => {
               // game.toggle_pause();
             }
+            Event::KeyDown {
+              keycode: Some(Keycode::LCtrl),
+              ..
+            }
+            | Event::KeyDown {
+              keycode: Some(Keycode::RCtrl),
+              ..
+            } => {
+              pinning = true;
+            }
+            Event::KeyUp {
+              keycode: Some(Keycode::LCtrl),
+              ..
+            }
+            | Event::KeyUp {
+              keycode: Some(Keycode::RCtrl),
+              ..
+            } => {
+              pinning = false;
+            }
+            Event::MouseButtonDown {
+              x,
+              y,
+              mouse_btn: MouseButton::Left,
+              ..
+            } if pinning => {
+              if let Some((x, y)) =
+                graphics::cell_at(x, y, args.size, args.pixel_size, args.topology)
+              {
+                if !state_ref_for_events.unpin(x, y) {
+                  if let Err(e) = state_ref_for_events.pin(x, y) {
+                    println!("{}", e);
+                  }
+                }
+              }
+            }
             Event::MouseButtonDown {
               x,
               y,
               mouse_btn: MouseButton::Left,
               ..
             } => {
-              let x = (x as u32) / args.pixel_size;
-              let y = (y as u32) / args.pixel_size;
-              mutations_ref_for_graphics.lock().unwrap().insert(
-                0,
-                Mutation {
-                  x,
-                  y,
-                  concept: Concept::Rose,
-                },
-              );
+              if let Some((x, y)) =
+                graphics::cell_at(x, y, args.size, args.pixel_size, args.topology)
+              {
+                mutations_ref_for_graphics
+                  .lock()
+                  .unwrap()
+                  .push_back(vec![Mutation::plant(x, y, Concept::Rose)]);
+              }
             }
             Event::MouseMotion {
               x, y, mousestate, ..
             } => {
-              if mousestate.left() {
-                let x = (x as u32) / args.pixel_size;
-                let y = (y as u32) / args.pixel_size;
-                mutations_ref_for_graphics.lock().unwrap().insert(
-                  0,
-                  Mutation {
-                    x,
-                    y,
-                    concept: Concept::Rose,
-                  },
-                );
+              if mousestate.left() && !pinning {
+                if let Some((x, y)) =
+                  graphics::cell_at(x, y, args.size, args.pixel_size, args.topology)
+                {
+                  mutations_ref_for_graphics
+                    .lock()
+                    .unwrap()
+                    .push_back(vec![Mutation::plant(x, y, Concept::Rose)]);
+                }
               }
             }
             _ => {}
@@ -183,8 +1427,42 @@ fn rocket() -> _ {
   }
 
   rocket::build()
-    .manage(state_ref_for_web)
+    .manage(Garden::new(state_ref_for_web))
     .manage(mutations_ref_for_web)
-    .mount("/", routes![index, garden, mutate])
+    .manage(snd_commands)
+    .manage(access)
+    .mount(
+      "/",
+      routes![
+        index,
+        garden,
+        framed_garden,
+        garden_png,
+        pyramid,
+        tile,
+        stream,
+        cell,
+        region,
+        density_grid,
+        status,
+        cluster_summary,
+        all_clusters,
+        mutate,
+        traces,
+        pin,
+        unpin,
+        admin_status,
+        pause,
+        resume,
+        step,
+        rate,
+        reset,
+        save_snapshot,
+        load_snapshot,
+        set_rules,
+        resize,
+        preflight
+      ],
+    )
     .attach(CORS)
 }
@@ -0,0 +1,150 @@
+//! Pictures of the garden for `/garden.png`, coloured like the window, so
+//! pages and bots can show the garden without decoding frames themselves.
+
+use crate::game::CellState;
+use crate::game::Frame;
+use crate::game::Terrain;
+use crate::game::Topology;
+use crate::graphics;
+use flate2::write::ZlibEncoder;
+use std::io::Write;
+
+/// Largest side of a picture, in pixels.
+pub const MAX_SIDE: u32 = 4096;
+
+/// What the colour of each cell shows.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Overlay {
+  /// The window's palette.
+  Concept,
+  /// Blood from the lowest in the picture, black, to the highest, white.
+  Blood,
+  /// Joy, as blood.
+  Joy,
+}
+
+impl Overlay {
+  pub fn parse(name: &str) -> Option<Overlay> {
+    match name {
+      "concept" => Some(Overlay::Concept),
+      "blood" => Some(Overlay::Blood),
+      "joy" => Some(Overlay::Joy),
+      _ => None,
+    }
+  }
+}
+
+/// Black through red and yellow to white as `t` goes from 0 to 1.
+fn heat(t: f32) -> [u8; 3] {
+  let channel = |offset: f32| ((t * 3.0 - offset).clamp(0.0, 1.0) * 255.0) as u8;
+  [channel(0.0), channel(1.0), channel(2.0)]
+}
+
+/// An RGB picture, row by row.
+pub struct Picture {
+  pub width: u32,
+  pub height: u32,
+  pixels: Vec<u8>,
+}
+
+impl Picture {
+  /// A `width` by `height` picture all of `color`.
+  pub fn new(width: u32, height: u32, color: [u8; 3]) -> Picture {
+    Picture {
+      width,
+      height,
+      pixels: color.repeat((width * height) as usize),
+    }
+  }
+
+  /// Paint the `size` by `size` square from (`left`, `top`) in `color`.
+  pub fn fill(&mut self, left: u32, top: u32, size: u32, color: [u8; 3]) {
+    for y in top..top + size {
+      let start = ((y * self.width + left) * 3) as usize;
+      for pixel in self.pixels[start..start + (size * 3) as usize].chunks_exact_mut(3) {
+        pixel.copy_from_slice(&color);
+      }
+    }
+  }
+
+  /// The `w` by `h` cells from (`x`, `y`) of `frame`, `scale` pixels a side
+  /// each. Odd rows of hexagonal worlds are shifted as in the window.
+  pub fn render(
+    frame: &Frame,
+    terrain: &[Terrain],
+    world_width: u32,
+    topology: Topology,
+    (x, y, w, h): (u32, u32, u32, u32),
+    scale: u32,
+    overlay: Overlay,
+  ) -> Picture {
+    let cells = frame.region(world_width, x, y, w, h);
+    let field = |cell: &CellState| match overlay {
+      Overlay::Blood => cell.blood,
+      _ => cell.joy,
+    };
+    let low = cells.iter().map(field).min().unwrap_or(0);
+    let high = cells.iter().map(field).max().unwrap_or(0);
+
+    let soil = graphics::terrain_color(Terrain::Plain);
+    let mut picture = Picture::new(
+      w * scale + graphics::row_shift(1, scale, topology),
+      h * scale,
+      [soil.r, soil.g, soil.b],
+    );
+    for (i, cell) in cells.iter().enumerate() {
+      let (column, row) = (i as u32 % w, i as u32 / w);
+      let color = match overlay {
+        Overlay::Concept => {
+          let ground = terrain[(x + column + (y + row) * world_width) as usize];
+          let color = graphics::cell_color(cell, ground);
+          [color.r, color.g, color.b]
+        }
+        _ if high == low => heat(0.0),
+        _ => heat((field(cell) as f32 - low as f32) / (high as f32 - low as f32)),
+      };
+
+      let left = column * scale + graphics::row_shift(y + row, scale, topology);
+      picture.fill(left, row * scale, scale, color);
+    }
+    picture
+  }
+
+  /// The picture as an 8-bit RGB PNG.
+  pub fn png(&self) -> Vec<u8> {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&self.width.to_be_bytes());
+    header.extend_from_slice(&self.height.to_be_bytes());
+    // Bit depth 8, truecolour, default compression and filtering, no interlace.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // Every row starts with its filter type, none.
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::fast());
+    for row in self.pixels.chunks_exact((self.width * 3) as usize) {
+      encoder
+        .write_all(&[0])
+        .and_then(|_| encoder.write_all(row))
+        .expect("compressing into memory cannot fail");
+    }
+    let data = encoder
+      .finish()
+      .expect("compressing into memory cannot fail");
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"IDAT", &data);
+    chunk(&mut out, b"IEND", &[]);
+    out
+  }
+}
+
+/// Append a PNG chunk: length, type, data and the CRC of type and data.
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+  out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+  out.extend_from_slice(kind);
+  out.extend_from_slice(data);
+  let mut crc = crc32fast::Hasher::new();
+  crc.update(kind);
+  crc.update(data);
+  out.extend_from_slice(&crc.finalize().to_be_bytes());
+}
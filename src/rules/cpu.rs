@@ -0,0 +1,435 @@
+use super::{Aggregate, AssignOp, BinOp, Builtin, Expr, Field, Neighbours, RuleSet, Stmt};
+use crate::game::CellLayout;
+use crate::game::CellState;
+use crate::game::Concept;
+use crate::game::Mutation;
+use crate::game::Terrain;
+use crate::game::Topology;
+use crate::seasons::SeasonRules;
+
+// Mirrors of the prelude's tables and helpers. Keep them in step with `prelude.comp`.
+const BIRTH: [i32; 3] = [3, 2, 2];
+const SURVIVE_MIN: [i32; 3] = [2, 1, 3];
+const SURVIVE_MAX: [i32; 3] = [3, 2, 4];
+const CROWD: [i32; 3] = [7, 3, 5];
+
+/// The previous tick, as the shader sees it.
+pub struct World<'a> {
+  pub cells: &'a [CellState],
+  pub terrain: &'a [Terrain],
+  pub width: u32,
+  pub topology: Topology,
+  pub season: SeasonRules,
+  pub tick: u32,
+  /// How the shader stores cells, which may saturate their fields.
+  pub layout: CellLayout,
+}
+
+fn to_axial(x: i32, y: i32) -> (i32, i32) {
+  (x - (y - (y & 1)) / 2, y)
+}
+
+fn ring_distance(topology: Topology, x: i32, y: i32, peek_x: i32, peek_y: i32) -> i32 {
+  let (dx, dy) = (peek_x - x, peek_y - y);
+  match topology {
+    Topology::VonNeumann => dx.abs() + dy.abs(),
+    Topology::Hexagonal => {
+      let (a, b) = (to_axial(peek_x, peek_y), to_axial(x, y));
+      let (dq, dr) = (a.0 - b.0, a.1 - b.1);
+      (dq.abs() + dr.abs() + (dq + dr).abs()) / 2
+    }
+    Topology::Moore => dx.abs().max(dy.abs()),
+  }
+}
+
+fn cells_within(topology: Topology, radius: i32) -> i32 {
+  if radius < 0 {
+    return 0;
+  }
+  match topology {
+    Topology::VonNeumann => 2 * radius * (radius + 1) + 1,
+    Topology::Hexagonal => 3 * radius * (radius + 1) + 1,
+    Topology::Moore => (2 * radius + 1) * (2 * radius + 1),
+  }
+}
+
+fn ring_cells(topology: Topology, outer: i32, inner: i32) -> i32 {
+  cells_within(topology, outer) - cells_within(topology, inner - 1)
+}
+
+/// One cell's evaluation: the working copy of the square plus `let` slots.
+struct Cell<'w, 'a> {
+  world: &'w World<'a>,
+  x: i32,
+  y: i32,
+  ground: Terrain,
+  concept: Concept,
+  blood: i32,
+  joy: i32,
+  slots: Vec<i32>,
+}
+
+impl<'w, 'a> Cell<'w, 'a> {
+  fn scan(&self, scan: &Neighbours) -> i32 {
+    let world = self.world;
+    let mut total: i32 = 0;
+    for i in -scan.outer..=scan.outer {
+      for j in -scan.outer..=scan.outer {
+        let (peek_x, peek_y) = (self.x + i, self.y + j);
+        let peek_distance = ring_distance(world.topology, self.x, self.y, peek_x, peek_y);
+        if peek_distance < scan.inner
+          || peek_distance > scan.outer
+          || peek_x < 0
+          || peek_y < 0
+          || peek_x >= world.width as i32
+          || peek_y >= world.width as i32
+        {
+          continue;
+        }
+        let cell = world.cells[(peek_x + peek_y * world.width as i32) as usize];
+        if !scan.concepts.is_empty() && !scan.concepts.contains(&cell.concept) {
+          continue;
+        }
+        match scan.aggregate {
+          Aggregate::Count => total = total.wrapping_add(1),
+          Aggregate::Any => return 1,
+          Aggregate::Sum(Field::Blood) => total = total.wrapping_add(cell.blood),
+          Aggregate::Sum(Field::Joy) => total = total.wrapping_add(cell.joy),
+        }
+      }
+    }
+    total
+  }
+
+  fn builtin(&self, builtin: Builtin) -> i32 {
+    let world = self.world;
+    let topology = world.topology as usize;
+    match builtin {
+      Builtin::Concept => self.concept as i32,
+      Builtin::Blood => self.blood,
+      Builtin::Joy => self.joy,
+      Builtin::Terrain => self.ground as i32,
+      Builtin::Topology => world.topology as i32,
+      Builtin::Tick => world.tick as i32,
+      Builtin::X => self.x,
+      Builtin::Y => self.y,
+      Builtin::Width => world.width as i32,
+      Builtin::Sprout => world.season.sprout,
+      Builtin::JoyGain => world.season.joy_gain,
+      Builtin::DogwoodDecay => world.season.dogwood_decay,
+      Builtin::Birth => BIRTH[topology],
+      Builtin::SurviveMin => SURVIVE_MIN[topology],
+      Builtin::SurviveMax => SURVIVE_MAX[topology],
+      Builtin::Crowd => CROWD[topology],
+    }
+  }
+
+  fn eval(&self, e: &Expr) -> i32 {
+    match e {
+      Expr::Int(value) => *value,
+      Expr::Var(slot) => self.slots[*slot],
+      Expr::Builtin(b) => self.builtin(*b),
+      Expr::Neg(value) => self.eval(value).wrapping_neg(),
+      Expr::Not(value) => (self.eval(value) == 0) as i32,
+      Expr::Binary(BinOp::And, a, b) => (self.eval(a) != 0 && self.eval(b) != 0) as i32,
+      Expr::Binary(BinOp::Or, a, b) => (self.eval(a) != 0 || self.eval(b) != 0) as i32,
+      Expr::Binary(op, a, b) => {
+        let (a, b) = (self.eval(a), self.eval(b));
+        match op {
+          BinOp::Add => a.wrapping_add(b),
+          BinOp::Sub => a.wrapping_sub(b),
+          BinOp::Mul => a.wrapping_mul(b),
+          // As `divide` in `rules.comp`: zero where GLSL's is undefined.
+          BinOp::Div => a.checked_div(b).unwrap_or(0),
+          BinOp::Eq => (a == b) as i32,
+          BinOp::Ne => (a != b) as i32,
+          BinOp::Lt => (a < b) as i32,
+          BinOp::Le => (a <= b) as i32,
+          BinOp::Gt => (a > b) as i32,
+          BinOp::Ge => (a >= b) as i32,
+          BinOp::And | BinOp::Or => unreachable!(),
+        }
+      }
+      Expr::Neighbours(scan) => self.scan(scan),
+      Expr::Cells(outer, inner) => ring_cells(self.world.topology, *outer, *inner),
+      Expr::Scaled(threshold, outer, inner) => {
+        self
+          .eval(threshold)
+          .wrapping_mul(ring_cells(self.world.topology, *outer, *inner))
+          / ring_cells(Topology::Moore, *outer, *inner)
+      }
+      Expr::Min(a, b) => self.eval(a).min(self.eval(b)),
+      Expr::Max(a, b) => self.eval(a).max(self.eval(b)),
+      Expr::Abs(value) => self.eval(value).wrapping_abs(),
+    }
+  }
+
+  fn run(&mut self, body: &[Stmt]) {
+    for stmt in body {
+      match stmt {
+        Stmt::Let(slot, value) => self.slots[*slot] = self.eval(value),
+        Stmt::Assign(field, op, value) => {
+          let value = self.eval(value);
+          let target = match field {
+            Field::Blood => &mut self.blood,
+            Field::Joy => &mut self.joy,
+          };
+          *target = match op {
+            AssignOp::Set => value,
+            AssignOp::Add => target.wrapping_add(value),
+            AssignOp::Sub => target.wrapping_sub(value),
+          };
+        }
+        Stmt::SetConcept(concept) => self.concept = *concept,
+        Stmt::Dust => {
+          self.concept = Concept::Soil;
+          self.blood = 0;
+          self.joy = 0;
+        }
+        // Only the rule pass traces branches.
+        Stmt::Branch(_) => {}
+        Stmt::If(branches, otherwise) => {
+          match branches
+            .iter()
+            .find(|(condition, _)| self.eval(condition) != 0)
+          {
+            Some((_, body)) => self.run(body),
+            None => {
+              if let Some(body) = otherwise {
+                self.run(body);
+              }
+            }
+          }
+        }
+      }
+    }
+  }
+}
+
+pub fn step(rules: &RuleSet, world: &World, mutations: &[Mutation]) -> Vec<CellState> {
+  let width = world.width as usize;
+  let mut next = Vec::with_capacity(world.cells.len());
+
+  for (idx, square) in world.cells.iter().enumerate() {
+    let (x, y) = ((idx % width) as u32, (idx / width) as u32);
+    let mut mutated = None;
+    for mutation in mutations.iter().filter(|m| m.x == x && m.y == y) {
+      mutated = Some(mutation.apply(&mutated.unwrap_or(*square)));
+    }
+    if let Some(cell) = mutated {
+      next.push(world.layout.round(cell));
+      continue;
+    }
+
+    let block = match rules
+      .blocks
+      .iter()
+      .find(|block| block.concepts.contains(&square.concept))
+    {
+      Some(block) => block,
+      None => {
+        next.push(*square);
+        continue;
+      }
+    };
+
+    let mut cell = Cell {
+      world,
+      x: x as i32,
+      y: y as i32,
+      ground: world.terrain[idx],
+      concept: square.concept,
+      blood: square.blood,
+      joy: square.joy,
+      slots: vec![0; rules.variables.len()],
+    };
+    cell.run(&block.body);
+    next.push(world.layout.round(CellState {
+      concept: cell.concept,
+      blood: cell.blood,
+      joy: cell.joy,
+    }));
+  }
+
+  next
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::rules::RuleSet;
+
+  fn cell(concept: Concept) -> CellState {
+    CellState {
+      concept,
+      blood: 0,
+      joy: 0,
+    }
+  }
+
+  /// One tick of `source` over `cells`, a square world of plain terrain.
+  fn tick(
+    source: &str,
+    cells: &[CellState],
+    topology: Topology,
+    layout: CellLayout,
+    mutations: &[Mutation],
+  ) -> Vec<CellState> {
+    let rules = RuleSet::parse(source).unwrap();
+    let world = World {
+      cells,
+      terrain: &vec![Terrain::Plain; cells.len()],
+      width: (cells.len() as f64).sqrt() as u32,
+      topology,
+      season: SeasonRules::default(),
+      tick: 0,
+      layout,
+    };
+    step(&rules, &world, mutations)
+  }
+
+  /// `expr` as a lone Soil cell in a Moore world evaluates it.
+  fn value(expr: &str) -> i32 {
+    let source = format!("on Soil {{ blood = {} }}", expr);
+    tick(
+      &source,
+      &[cell(Concept::Soil)],
+      Topology::Moore,
+      CellLayout::Wide,
+      &[],
+    )[0]
+      .blood
+  }
+
+  #[test]
+  fn arithmetic_wraps() {
+    assert_eq!(value("2147483647 + 1"), i32::MIN);
+    assert_eq!(value("-(-2147483647 - 1)"), i32::MIN);
+    assert_eq!(value("65536 * 65536"), 0);
+  }
+
+  #[test]
+  fn division_truncates_and_never_fails() {
+    assert_eq!(value("7 / 2"), 3);
+    assert_eq!(value("-7 / 2"), -3);
+    assert_eq!(value("7 / 0"), 0);
+    assert_eq!(value("(-2147483647 - 1) / -1"), 0);
+  }
+
+  #[test]
+  fn logic_yields_zero_or_one() {
+    assert_eq!(value("2 and 3"), 1);
+    assert_eq!(value("0 or -1"), 1);
+    assert_eq!(value("not 5"), 0);
+    assert_eq!(value("3 < 2"), 0);
+    assert_eq!(value("min(3, -2) + max(3, -2) + abs(-4)"), 5);
+  }
+
+  #[test]
+  fn rings_follow_the_topology() {
+    assert_eq!(ring_cells(Topology::Moore, 1, 1), 8);
+    assert_eq!(ring_cells(Topology::VonNeumann, 1, 1), 4);
+    assert_eq!(ring_cells(Topology::Hexagonal, 1, 1), 6);
+    assert_eq!(ring_cells(Topology::Moore, 2, 2), 16);
+    assert_eq!(value("cells(2, 1)"), 24);
+    assert_eq!(value("scaled(12, 2, 1)"), 12);
+  }
+
+  #[test]
+  fn scans_stay_within_the_world() {
+    let cells = vec![cell(Concept::Sunflower); 9];
+    let source = "on Sunflower { blood = count(Sunflower, 1, 1) }";
+    let blood: Vec<i32> = tick(source, &cells, Topology::Moore, CellLayout::Wide, &[])
+      .iter()
+      .map(|cell| cell.blood)
+      .collect();
+    assert_eq!(blood, vec![3, 5, 3, 5, 8, 5, 3, 5, 3]);
+  }
+
+  #[test]
+  fn scans_follow_the_topology() {
+    let cells = vec![cell(Concept::Soil); 9];
+    let source = "on Soil { blood = count(*, 1, 1) + 100 * scaled(12, 1, 1) }";
+    for (topology, expected) in [
+      (Topology::Moore, 8 + 1200),
+      (Topology::VonNeumann, 4 + 600),
+      (Topology::Hexagonal, 6 + 900),
+    ] {
+      let next = tick(source, &cells, topology, CellLayout::Wide, &[]);
+      assert_eq!(next[4].blood, expected);
+    }
+  }
+
+  #[test]
+  fn the_first_true_arm_runs() {
+    let source = "on Soil { if 0 { blood = 1 } else if 2 { blood = 2 } else if 3 { blood = 3 } }";
+    assert_eq!(
+      tick(
+        source,
+        &[cell(Concept::Soil)],
+        Topology::Moore,
+        CellLayout::Wide,
+        &[]
+      )[0]
+        .blood,
+      2
+    );
+  }
+
+  #[test]
+  fn mutations_replace_the_rules() {
+    let cells = vec![cell(Concept::Soil); 4];
+    let mutation = Mutation {
+      blood: Some(5),
+      ..Mutation::plant(1, 0, Concept::Elder)
+    };
+    let next = tick(
+      "on Soil { concept = Rose\n joy = 7 }",
+      &cells,
+      Topology::Moore,
+      CellLayout::Wide,
+      &[mutation],
+    );
+    assert!(next[1].concept == Concept::Elder);
+    assert_eq!((next[1].blood, next[1].joy), (5, 0));
+    assert!(next[0].concept == Concept::Rose);
+    assert_eq!(next[0].joy, 7);
+  }
+
+  #[test]
+  fn mutations_of_one_cell_apply_in_order() {
+    let mutations = [
+      Mutation {
+        blood: Some(5),
+        joy: Some(6),
+        ..Mutation::plant(0, 0, Concept::Rose)
+      },
+      Mutation {
+        joy: Some(9),
+        ..Mutation::plant(0, 0, Concept::Elder)
+      },
+    ];
+    let next = tick(
+      "",
+      &[cell(Concept::Soil)],
+      Topology::Moore,
+      CellLayout::Wide,
+      &mutations,
+    );
+    assert!(next[0].concept == Concept::Elder);
+    assert_eq!((next[0].blood, next[0].joy), (5, 9));
+  }
+
+  #[test]
+  fn packed_cells_saturate_when_stored() {
+    let source = "on Soil { blood = 40000\n joy = -3 }";
+    let next = tick(
+      source,
+      &[cell(Concept::Soil)],
+      Topology::Moore,
+      CellLayout::Packed,
+      &[],
+    );
+    assert_eq!((next[0].blood, next[0].joy), (32767, 0));
+  }
+}
@@ -0,0 +1,522 @@
+use super::{
+  Aggregate, AssignOp, BinOp, Builtin, Channel, Expr, Field, Neighbours, RuleSet, Stmt,
+  TRACED_BRANCHES,
+};
+use crate::density::DENSITY_WORDS;
+use crate::game::CellLayout;
+use crate::game::Concept;
+use crate::game::MAX_MUTATIONS;
+use crate::trace::{MAX_WATCHPOINTS, TRACE_WORDS};
+use std::fmt::Write;
+
+fn field(field: Field) -> &'static str {
+  match field {
+    Field::Blood => "blood",
+    Field::Joy => "joy",
+  }
+}
+
+/// The prelude's constant for each concept.
+fn concept(concept: Concept) -> &'static str {
+  match concept {
+    Concept::Soil => "Soil",
+    Concept::Sunflower => "Sunflower",
+    Concept::Rose => "Rose",
+    Concept::Dogwood => "Dogwood",
+    Concept::Elder => "Elder",
+    Concept::Thistle => "Thistle",
+  }
+}
+
+fn builtin(builtin: Builtin) -> &'static str {
+  match builtin {
+    Builtin::Concept => "square.concept",
+    Builtin::Blood => "square.blood",
+    Builtin::Joy => "square.joy",
+    Builtin::Terrain => "ground",
+    Builtin::Topology => "params.topology",
+    Builtin::Tick => "int(params.tick)",
+    Builtin::X => "int(x)",
+    Builtin::Y => "int(y)",
+    Builtin::Width => "int(params.world_width)",
+    Builtin::Sprout => "params.season.sprout",
+    Builtin::JoyGain => "params.season.joy_gain",
+    Builtin::DogwoodDecay => "params.season.dogwood_decay",
+    Builtin::Birth => "BIRTH[params.topology]",
+    Builtin::SurviveMin => "SURVIVE_MIN[params.topology]",
+    Builtin::SurviveMax => "SURVIVE_MAX[params.topology]",
+    Builtin::Crowd => "CROWD[params.topology]",
+  }
+}
+
+fn variable(rules: &RuleSet, slot: usize) -> String {
+  format!("v{}_{}", slot, rules.variables[slot])
+}
+
+fn truthy(rules: &RuleSet, e: &Expr) -> String {
+  format!("({} != 0)", expr(rules, e))
+}
+
+fn expr(rules: &RuleSet, e: &Expr) -> String {
+  match e {
+    Expr::Int(value) => format!("{}", value),
+    Expr::Var(slot) => variable(rules, *slot),
+    Expr::Builtin(b) => builtin(*b).to_string(),
+    Expr::Neg(value) => format!("(-{})", expr(rules, value)),
+    Expr::Not(value) => format!("int(!{})", truthy(rules, value)),
+    Expr::Binary(op, a, b) => {
+      let (a_src, b_src) = (expr(rules, a), expr(rules, b));
+      match op {
+        BinOp::Add => format!("({} + {})", a_src, b_src),
+        BinOp::Sub => format!("({} - {})", a_src, b_src),
+        BinOp::Mul => format!("({} * {})", a_src, b_src),
+        BinOp::Div => format!("divide({}, {})", a_src, b_src),
+        BinOp::Eq => format!("int({} == {})", a_src, b_src),
+        BinOp::Ne => format!("int({} != {})", a_src, b_src),
+        BinOp::Lt => format!("int({} < {})", a_src, b_src),
+        BinOp::Le => format!("int({} <= {})", a_src, b_src),
+        BinOp::Gt => format!("int({} > {})", a_src, b_src),
+        BinOp::Ge => format!("int({} >= {})", a_src, b_src),
+        BinOp::And => format!("int({} && {})", truthy(rules, a), truthy(rules, b)),
+        BinOp::Or => format!("int({} || {})", truthy(rules, a), truthy(rules, b)),
+      }
+    }
+    Expr::Neighbours(scan) => format!("neighbours_{}(x, y)", scan.id),
+    Expr::Cells(outer, inner) => format!("ring_cells(params.topology, {}, {})", outer, inner),
+    Expr::Scaled(threshold, outer, inner) => {
+      format!("scaled({}, {}, {})", expr(rules, threshold), outer, inner)
+    }
+    Expr::Min(a, b) => format!("min({}, {})", expr(rules, a), expr(rules, b)),
+    Expr::Max(a, b) => format!("max({}, {})", expr(rules, a), expr(rules, b)),
+    Expr::Abs(value) => format!("abs({})", expr(rules, value)),
+  }
+}
+
+fn indent(out: &mut String, depth: usize) {
+  for _ in 0..depth {
+    out.push_str("  ");
+  }
+}
+
+fn statements(rules: &RuleSet, out: &mut String, body: &[Stmt], depth: usize) {
+  for stmt in body {
+    indent(out, depth);
+    match stmt {
+      Stmt::Let(slot, value) => {
+        let _ = writeln!(
+          out,
+          "int {} = {};",
+          variable(rules, *slot),
+          expr(rules, value)
+        );
+      }
+      Stmt::Assign(f, op, value) => {
+        let op = match op {
+          AssignOp::Set => "=",
+          AssignOp::Add => "+=",
+          AssignOp::Sub => "-=",
+        };
+        let _ = writeln!(out, "square.{} {} {};", field(*f), op, expr(rules, value));
+      }
+      Stmt::SetConcept(c) => {
+        let _ = writeln!(out, "square.concept = {};", concept(*c));
+      }
+      Stmt::Dust => out.push_str("to_dust(square);\n"),
+      Stmt::Branch(id) if *id < TRACED_BRANCHES => {
+        let _ = writeln!(out, "fired |= 1u << {}u;", id);
+      }
+      Stmt::Branch(id) => {
+        let _ = writeln!(out, "// branch {} is not traced", id);
+      }
+      Stmt::If(branches, otherwise) => {
+        for (i, (condition, body)) in branches.iter().enumerate() {
+          if i > 0 {
+            out.push_str(" else ");
+          }
+          let _ = writeln!(out, "if {} {{", truthy(rules, condition));
+          statements(rules, out, body, depth + 1);
+          indent(out, depth);
+          out.push('}');
+        }
+        if let Some(body) = otherwise {
+          out.push_str(" else {\n");
+          statements(rules, out, body, depth + 1);
+          indent(out, depth);
+          out.push('}');
+        }
+        out.push('\n');
+      }
+    }
+  }
+}
+
+fn concept_test(subject: &str, concepts: &[Concept]) -> String {
+  concepts
+    .iter()
+    .map(|c| format!("{} == {}", subject, concept(*c)))
+    .collect::<Vec<String>>()
+    .join(" || ")
+}
+
+fn scan(out: &mut String, scan: &Neighbours, channel: Option<usize>) {
+  if let Some(channel) = channel {
+    let total = format!(
+      "table_ring({}, x, y, {}, {})",
+      channel, scan.outer, scan.inner
+    );
+    let _ = write!(
+      out,
+      "int neighbours_{}(uint x, uint y) {{\n  return {};\n}}\n\n",
+      scan.id,
+      match scan.aggregate {
+        Aggregate::Any => format!("int({} > 0)", total),
+        Aggregate::Count | Aggregate::Sum(_) => total,
+      }
+    );
+    return;
+  }
+
+  let accumulate = match scan.aggregate {
+    Aggregate::Count => "total += 1;".to_string(),
+    Aggregate::Any => "return 1;".to_string(),
+    Aggregate::Sum(f) => format!("total += cell.{};", field(f)),
+  };
+  let filter = if scan.concepts.is_empty() {
+    "true".to_string()
+  } else {
+    concept_test("cell.concept", &scan.concepts)
+  };
+
+  let _ = write!(
+    out,
+    "int neighbours_{id}(uint x, uint y) {{
+  int total = 0;
+  for (int i = -{outer}; i <= {outer}; i++) {{
+    for (int j = -{outer}; j <= {outer}; j++) {{
+      int peek_x = int(x) + i;
+      int peek_y = int(y) + j;
+      int peek_distance = ring_distance(int(x), int(y), peek_x, peek_y);
+      if (peek_distance >= {inner} && peek_distance <= {outer} && in_world(peek_x, peek_y)) {{
+        CellState cell = peek(peek_x, peek_y);
+        if ({filter}) {{
+          {accumulate}
+        }}
+      }}
+    }}
+  }}
+  return total;
+}}
+
+",
+    id = scan.id,
+    outer = scan.outer,
+    inner = scan.inner,
+    filter = filter,
+    accumulate = accumulate,
+  );
+}
+
+/// Generate the scan helpers and `apply_rules` that the rule pass's `main` calls.
+/// `lookups` gives the table channel each scan reads, if any.
+pub fn generate(rules: &RuleSet, lookups: &[Option<usize>]) -> String {
+  let mut out = String::new();
+  for (s, channel) in rules.scans.iter().zip(lookups) {
+    scan(&mut out, s, *channel);
+  }
+
+  out.push_str(
+    "void apply_rules(inout CellState square, inout uint fired, uint x, uint y, int ground) {\n",
+  );
+  for (i, block) in rules.blocks.iter().enumerate() {
+    indent(&mut out, 1);
+    if i > 0 {
+      out.push_str("else ");
+    }
+    let _ = writeln!(
+      out,
+      "if ({}) {{",
+      concept_test("square.concept", &block.concepts)
+    );
+    statements(rules, &mut out, &block.body, 2);
+    indent(&mut out, 1);
+    out.push_str("}\n");
+  }
+  out.push_str("}\n");
+  out
+}
+
+/// Generate the declarations the tables pass's `main` expects.
+pub fn channels(channels: &[Channel], rows: bool) -> String {
+  let mut out = String::new();
+  let _ = writeln!(out, "const bool ROWS = {};", rows);
+  let _ = writeln!(out, "const int CHANNELS = {};\n", channels.len());
+
+  out.push_str("int channel_value(int channel, CellState cell) {\n");
+  for (i, channel) in channels.iter().enumerate() {
+    let value = match channel.field {
+      Some(f) => format!("cell.{}", field(f)),
+      None => "1".to_string(),
+    };
+    let _ = writeln!(out, "  if (channel == {}) {{", i);
+    if channel.concepts.is_empty() {
+      let _ = writeln!(out, "    return {};", value);
+    } else {
+      let _ = writeln!(
+        out,
+        "    return ({}) ? {} : 0;",
+        concept_test("cell.concept", &channel.concepts),
+        value
+      );
+    }
+    out.push_str("  }\n");
+  }
+  out.push_str("  return 0;\n}\n");
+  out
+}
+
+/// Declare the limits the shaders share with the runner, so the two cannot
+/// drift apart.
+pub fn limits() -> String {
+  format!(
+    "const uint MAX_MUTATIONS = {}u;
+const uint MAX_WATCHPOINTS = {}u;
+const uint TRACE_WORDS = {}u;
+const uint DENSITY_WORDS = {}u;
+",
+    MAX_MUTATIONS, MAX_WATCHPOINTS, TRACE_WORDS, DENSITY_WORDS
+  )
+}
+
+/// Declare `StoredCell` and its conversions for the prelude's cell buffers.
+/// Packing saturates exactly like `CellLayout::encode`.
+pub fn layout(layout: CellLayout) -> String {
+  match layout {
+    CellLayout::Wide => "\
+struct StoredCell {
+  int concept;
+  int blood;
+  int joy;
+};
+
+CellState unpack(StoredCell stored) {
+  return CellState(stored.concept, stored.blood, stored.joy);
+}
+
+StoredCell pack(CellState cell) {
+  return StoredCell(cell.concept, cell.blood, cell.joy);
+}
+"
+    .to_string(),
+    CellLayout::Packed => "\
+// The concept, blood and joy from the lowest byte up: 8, 16 and 8 bits.
+struct StoredCell {
+  uint bits;
+};
+
+CellState unpack(StoredCell stored) {
+  return CellState(
+    int(stored.bits & 0xFFu),
+    int(stored.bits << 8u) >> 16u,
+    int(stored.bits >> 24u)
+  );
+}
+
+StoredCell pack(CellState cell) {
+  uint blood = uint(clamp(cell.blood, -32768, 32767)) & 0xFFFFu;
+  uint joy = uint(clamp(cell.joy, 0, 255));
+  return StoredCell(uint(cell.concept) | (blood << 8u) | (joy << 24u));
+}
+"
+    .to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::game::{CellState, Terrain, Topology};
+  use crate::rules::{Counting, World};
+  use crate::seasons::SeasonRules;
+
+  /// Evaluates the arithmetic `expr` generates as GLSL would, panicking
+  /// wherever GLSL leaves the result undefined.
+  struct Glsl<'s> {
+    tokens: Vec<&'s str>,
+    pos: usize,
+  }
+
+  impl<'s> Glsl<'s> {
+    fn eval(source: &'s str) -> i32 {
+      let mut tokens = Vec::new();
+      let mut rest = source.trim_start();
+      while !rest.is_empty() {
+        let length = match rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_') {
+          Some(0) if ["==", "!=", "<=", ">=", "&&", "||"].contains(&&rest[..2.min(rest.len())]) => {
+            2
+          }
+          Some(0) => 1,
+          Some(end) => end,
+          None => rest.len(),
+        };
+        tokens.push(&rest[..length]);
+        rest = rest[length..].trim_start();
+      }
+      let mut glsl = Glsl { tokens, pos: 0 };
+      let value = glsl.value();
+      assert_eq!(glsl.pos, glsl.tokens.len(), "trailing tokens in {}", source);
+      value
+    }
+
+    fn next(&mut self) -> &'s str {
+      self.pos += 1;
+      self.tokens[self.pos - 1]
+    }
+
+    fn expect(&mut self, token: &str) {
+      assert_eq!(self.next(), token);
+    }
+
+    /// An operand, or two joined by an operator: `expr` parenthesizes the rest.
+    fn value(&mut self) -> i32 {
+      let a = self.operand();
+      let op = match self.tokens.get(self.pos) {
+        Some(&op) if !matches!(op, ")" | ",") => op,
+        _ => return a,
+      };
+      self.pos += 1;
+      let b = self.operand();
+      match op {
+        "+" => a.wrapping_add(b),
+        "-" => a.wrapping_sub(b),
+        "*" => a.wrapping_mul(b),
+        "/" => a
+          .checked_div(b)
+          .unwrap_or_else(|| panic!("{} / {} is undefined in GLSL", a, b)),
+        "==" => (a == b) as i32,
+        "!=" => (a != b) as i32,
+        "<" => (a < b) as i32,
+        "<=" => (a <= b) as i32,
+        ">" => (a > b) as i32,
+        ">=" => (a >= b) as i32,
+        "&&" => (a != 0 && b != 0) as i32,
+        "||" => (a != 0 || b != 0) as i32,
+        _ => panic!("unexpected operator {}", op),
+      }
+    }
+
+    fn operand(&mut self) -> i32 {
+      match self.next() {
+        "(" if self.tokens[self.pos] == "-" => {
+          self.pos += 1;
+          let value = self.operand().wrapping_neg();
+          self.expect(")");
+          value
+        }
+        "(" => {
+          let value = self.value();
+          self.expect(")");
+          value
+        }
+        "!" => (self.operand() == 0) as i32,
+        name if name.starts_with(|c: char| c.is_ascii_digit()) => name.parse().unwrap(),
+        name => {
+          self.expect("(");
+          let mut args = vec![self.value()];
+          while self.next() == "," {
+            args.push(self.value());
+          }
+          match (name, args.as_slice()) {
+            ("int", [a]) => *a,
+            ("abs", [a]) => a.wrapping_abs(),
+            ("min", [a, b]) => *a.min(b),
+            ("max", [a, b]) => *a.max(b),
+            // As `rules.comp` defines it.
+            ("divide", [a, b]) if *b == 0 || (*b == -1 && *a == i32::MIN) => 0,
+            ("divide", [a, b]) => a / b,
+            _ => panic!("unexpected call {}", name),
+          }
+        }
+      }
+    }
+  }
+
+  /// `expr` as the CPU evaluator and the generated GLSL compute it.
+  fn both(expr: &str) -> (i32, i32) {
+    let rules = RuleSet::parse(&format!("on Soil {{ blood = {} }}", expr)).unwrap();
+    let value = match &rules.blocks[0].body[0] {
+      Stmt::Assign(_, _, value) => value,
+      _ => unreachable!(),
+    };
+    let soil = CellState {
+      concept: Concept::Soil,
+      blood: 0,
+      joy: 0,
+    };
+    let world = World {
+      cells: &[soil],
+      terrain: &[Terrain::Plain],
+      width: 1,
+      topology: Topology::Moore,
+      season: SeasonRules::default(),
+      tick: 0,
+      layout: CellLayout::Wide,
+    };
+    (
+      rules.step(&world, &[])[0].blood,
+      Glsl::eval(&super::expr(&rules, value)),
+    )
+  }
+
+  const OPERANDS: [&str; 7] = ["0", "1", "-1", "7", "-7", "2147483647", "-2147483647 - 1"];
+
+  #[test]
+  fn operators_match_the_cpu() {
+    let operators = [
+      "+", "-", "*", "/", "==", "!=", "<", "<=", ">", ">=", "and", "or",
+    ];
+    for op in operators {
+      for a in OPERANDS {
+        for b in OPERANDS {
+          let expr = format!("({}) {} ({})", a, op, b);
+          let (cpu, gpu) = both(&expr);
+          assert_eq!(cpu, gpu, "{}", expr);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn functions_match_the_cpu() {
+    for a in OPERANDS {
+      for expr in [
+        format!("-({})", a),
+        format!("not ({})", a),
+        format!("abs({})", a),
+      ] {
+        let (cpu, gpu) = both(&expr);
+        assert_eq!(cpu, gpu, "{}", expr);
+      }
+      for b in OPERANDS {
+        for f in ["min", "max"] {
+          let expr = format!("{}({}, {})", f, a, b);
+          let (cpu, gpu) = both(&expr);
+          assert_eq!(cpu, gpu, "{}", expr);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn division_by_scans_compiles() {
+    let rules = RuleSet::parse("on Rose { joy = sum(joy, *, 3, 1) / count(Rose, 3, 1) }").unwrap();
+    for topology in [Topology::Moore, Topology::VonNeumann, Topology::Hexagonal] {
+      for counting in [Counting::Scan, Counting::Tables] {
+        let source = rules.to_glsl(topology, counting, CellLayout::Wide);
+        assert!(source.contains("divide(neighbours_0(x, y), "));
+        if let Err(e) = rules.compile(topology, counting, CellLayout::Wide) {
+          panic!("{}", e);
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,398 @@
+//! A small rule language for the garden, compiled to the compute shader at
+//! startup and interpretable on the CPU with identical results.
+//!
+//! A rule file is a list of `on` blocks, each naming the concepts it applies to:
+//!
+//! ```text
+//! on Rose {
+//!   if sum(blood, *, 2, 1) >= scaled(12, 2, 1) {
+//!     concept = Dogwood
+//!     blood -= 60
+//!   }
+//! }
+//! ```
+//!
+//! Every value is an integer; comparisons and `and`/`or`/`not` yield 0 or 1
+//! and any non-zero value is true. Arithmetic wraps, and division truncates
+//! toward zero, giving 0 when dividing by zero or overflowing. Statements are
+//! `let name = expr`, `blood`/`joy` assignments (`=`, `+=`, `-=`),
+//! `concept = <Concept>`, `dust` and `if`/`else if`/`else`. Neighbourhood
+//! scans take a ring between two radii under the world's topology:
+//! `count(Rose | Dogwood, outer, inner)`, `any(Elder, outer, inner)` and
+//! `sum(blood | joy, <concepts> | *, outer, inner)`.
+//! See `garden.rules` for the built-in garden.
+//!
+//! On Moore worlds, wide scans can instead be answered from summed-area tables
+//! built by a prepass each tick, making a ring of any radius four lookups per box.
+//!
+//! Every arm of an `if` is a numbered branch. The rule pass notes which of the
+//! first `TRACED_BRANCHES` fired for watched cells, see `trace.rs`.
+//!
+//! Cells are stored in the world's `CellLayout`. Under the packed layout, blood
+//! and joy saturate when a tick's cells are stored, not while rules run.
+
+mod cpu;
+mod glsl;
+mod parser;
+
+use crate::game::CellLayout;
+use crate::game::CellState;
+use crate::game::Concept;
+use crate::game::Mutation;
+use crate::game::Topology;
+
+pub use cpu::World;
+
+pub const GARDEN_RULES: &str = include_str!("garden.rules");
+const PRELUDE: &str = include_str!("prelude.comp");
+const RULES_PASS: &str = include_str!("rules.comp");
+const TABLES_PASS: &str = include_str!("tables.comp");
+const CENSUS_PASS: &str = include_str!("census.comp");
+const DENSITY_PASS: &str = include_str!("density.comp");
+
+/// Branches past these many are not reported when tracing.
+pub const TRACED_BRANCHES: usize = 32;
+
+/// Narrower scans read their few cells directly even when counting with tables.
+const TABLE_MIN_RADIUS: i32 = 2;
+
+/// How the shader answers neighbourhood scans.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Counting {
+  /// Read every cell in the ring.
+  Scan,
+  /// Look ring totals up in summed-area tables. Only Moore rings are boxes,
+  /// so other topologies always scan.
+  Tables,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Field {
+  Blood,
+  Joy,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AssignOp {
+  Set,
+  Add,
+  Sub,
+}
+
+/// Read-only values every rule can see.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Builtin {
+  Concept,
+  Blood,
+  Joy,
+  Terrain,
+  Topology,
+  Tick,
+  X,
+  Y,
+  Width,
+  Sprout,
+  JoyGain,
+  DogwoodDecay,
+  Birth,
+  SurviveMin,
+  SurviveMax,
+  Crowd,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BinOp {
+  Add,
+  Sub,
+  Mul,
+  Div,
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+  And,
+  Or,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Aggregate {
+  Count,
+  Any,
+  Sum(Field),
+}
+
+/// A scan over the ring of cells between `inner` and `outer` (inclusive).
+/// An empty concept list matches every cell.
+#[derive(Clone)]
+pub struct Neighbours {
+  pub id: usize,
+  pub aggregate: Aggregate,
+  pub concepts: Vec<Concept>,
+  pub outer: i32,
+  pub inner: i32,
+}
+
+#[derive(Clone)]
+pub enum Expr {
+  Int(i32),
+  Var(usize),
+  Builtin(Builtin),
+  Neg(Box<Expr>),
+  Not(Box<Expr>),
+  Binary(BinOp, Box<Expr>, Box<Expr>),
+  Neighbours(Neighbours),
+  Cells(i32, i32),
+  Scaled(Box<Expr>, i32, i32),
+  Min(Box<Expr>, Box<Expr>),
+  Max(Box<Expr>, Box<Expr>),
+  Abs(Box<Expr>),
+}
+
+#[derive(Clone)]
+pub enum Stmt {
+  Let(usize, Expr),
+  Assign(Field, AssignOp, Expr),
+  SetConcept(Concept),
+  Dust,
+  If(Vec<(Expr, Vec<Stmt>)>, Option<Vec<Stmt>>),
+  /// First statement of every arm of an `if`, noting that it was taken.
+  Branch(usize),
+}
+
+/// An arm of an `if`, numbered in source order.
+#[derive(Clone)]
+pub struct Branch {
+  pub line: usize,
+  /// `if`, `else if` or `else`.
+  pub arm: &'static str,
+}
+
+impl std::fmt::Display for Branch {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "line {}: {}", self.line, self.arm)
+  }
+}
+
+#[derive(Clone)]
+pub struct Block {
+  pub concepts: Vec<Concept>,
+  pub body: Vec<Stmt>,
+}
+
+/// A quantity tabled for scans: the number of cells of the listed concepts,
+/// or the sum of a field over them. An empty list matches every cell.
+#[derive(Clone, PartialEq)]
+pub struct Channel {
+  pub field: Option<Field>,
+  pub concepts: Vec<Concept>,
+}
+
+/// Compiled SPIR-V for one run of the garden.
+#[derive(Clone)]
+pub struct Shaders {
+  pub rules: Vec<u32>,
+  /// The rows then columns passes building the tables, when any scan reads them.
+  pub tables: Option<(Vec<u32>, Vec<u32>)>,
+  pub channels: u32,
+  /// The pass hashing each tick, see `census.comp`.
+  pub census: Vec<u32>,
+  /// The pass summarizing blocks of the world on request, see `density.comp`.
+  pub density: Vec<u32>,
+  /// How the shaders store cells.
+  pub layout: CellLayout,
+  /// Labels of the rule branches, by the bit the rule pass traces them with.
+  pub branches: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct RuleSet {
+  blocks: Vec<Block>,
+  /// Names of every `let`, indexed by slot.
+  variables: Vec<String>,
+  /// Every neighbourhood scan, indexed by id.
+  scans: Vec<Neighbours>,
+  /// Every arm of every `if`, indexed by `Stmt::Branch`.
+  branches: Vec<Branch>,
+}
+
+impl RuleSet {
+  pub fn parse(source: &str) -> Result<RuleSet, String> {
+    parser::parse(source)
+  }
+
+  /// Channels worth tabling and, for each scan, the channel it reads if any.
+  fn channels(&self, topology: Topology, counting: Counting) -> (Vec<Channel>, Vec<Option<usize>>) {
+    let mut channels: Vec<Channel> = Vec::new();
+    let lookups = self
+      .scans
+      .iter()
+      .map(|scan| {
+        if counting != Counting::Tables
+          || topology != Topology::Moore
+          || scan.outer < TABLE_MIN_RADIUS
+        {
+          return None;
+        }
+        let channel = Channel {
+          field: match scan.aggregate {
+            Aggregate::Sum(field) => Some(field),
+            Aggregate::Count | Aggregate::Any => None,
+          },
+          concepts: scan.concepts.clone(),
+        };
+        Some(match channels.iter().position(|c| *c == channel) {
+          Some(existing) => existing,
+          None => {
+            channels.push(channel);
+            channels.len() - 1
+          }
+        })
+      })
+      .collect();
+    (channels, lookups)
+  }
+
+  /// The rule pass's compute shader source.
+  pub fn to_glsl(&self, topology: Topology, counting: Counting, layout: CellLayout) -> String {
+    let (_, lookups) = self.channels(topology, counting);
+    format!(
+      "{}{}",
+      prelude(layout),
+      RULES_PASS.replace("// @rules", &glsl::generate(self, &lookups))
+    )
+  }
+
+  /// Source of the rows (or columns) pass building the tables for `channels`.
+  fn tables_glsl(channels: &[Channel], rows: bool, layout: CellLayout) -> String {
+    format!(
+      "{}{}",
+      prelude(layout),
+      TABLES_PASS.replace("// @channels", &glsl::channels(channels, rows))
+    )
+  }
+
+  pub fn compile(
+    &self,
+    topology: Topology,
+    counting: Counting,
+    layout: CellLayout,
+  ) -> Result<Shaders, String> {
+    let (channels, _) = self.channels(topology, counting);
+    let tables = if channels.is_empty() {
+      None
+    } else {
+      Some((
+        to_spirv(&RuleSet::tables_glsl(&channels, true, layout))?,
+        to_spirv(&RuleSet::tables_glsl(&channels, false, layout))?,
+      ))
+    };
+
+    Ok(Shaders {
+      rules: to_spirv(&self.to_glsl(topology, counting, layout))?,
+      tables,
+      channels: channels.len() as u32,
+      census: to_spirv(&format!("{}{}", prelude(layout), CENSUS_PASS))?,
+      density: to_spirv(&format!("{}{}", prelude(layout), DENSITY_PASS))?,
+      layout,
+      branches: self
+        .branches
+        .iter()
+        .map(|branch| branch.to_string())
+        .collect(),
+    })
+  }
+
+  /// Advance every cell by one tick on the CPU, exactly as the shader would.
+  pub fn step(&self, world: &World, mutations: &[Mutation]) -> Vec<CellState> {
+    cpu::step(self, world, mutations)
+  }
+}
+
+/// The shared declarations, with cells stored in `layout`.
+fn prelude(layout: CellLayout) -> String {
+  PRELUDE
+    .replace("// @limits", &glsl::limits())
+    .replace("// @layout", &glsl::layout(layout))
+}
+
+fn to_spirv(source: &str) -> Result<Vec<u32>, String> {
+  use naga::back::spv;
+  use naga::front::glsl::{Frontend, Options};
+  use naga::valid::{Capabilities, ValidationFlags, Validator};
+
+  let module = Frontend::default()
+    .parse(&Options::from(naga::ShaderStage::Compute), source)
+    .map_err(|e| {
+      format!(
+        "Generated shader did not compile:\n{}",
+        e.emit_to_string(source)
+      )
+    })?;
+  let info = Validator::new(ValidationFlags::all(), Capabilities::empty())
+    .validate(&module)
+    .map_err(|e| format!("Generated shader is invalid: {:?}", e.into_inner()))?;
+
+  spv::write_vec(
+    &module,
+    &info,
+    &spv::Options::default(),
+    Some(&spv::PipelineOptions {
+      shader_stage: naga::ShaderStage::Compute,
+      entry_point: "main".to_string(),
+    }),
+  )
+  .map_err(|e| format!("Could not write SPIR-V: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::game::{Residency, Runner, Terrain};
+  use crate::seasons::Seasons;
+
+  /// Life, dividing by neighbour counts that are often zero or make the
+  /// division overflow.
+  const DIVIDING: &str = "
+on Soil, Sunflower {
+  let near = count(Sunflower, 1, 1)
+  let far = count(*, 3, 2)
+  joy = far / (near - 1)
+  blood = (-2147483647 - 1) / (near - 3)
+  if near == birth {
+    concept = Sunflower
+  } else if near > survive_max or near < survive_min {
+    concept = Soil
+  }
+}
+";
+
+  #[test]
+  #[ignore = "needs a Vulkan device"]
+  fn the_gpu_matches_the_cpu() {
+    let rules = RuleSet::parse(DIVIDING).unwrap();
+    let terrain = vec![Terrain::Plain; 64 * 64];
+    let seasons = Seasons {
+      period: 0,
+      phase: 0,
+      seasons: Vec::new(),
+    };
+    for topology in [Topology::Moore, Topology::VonNeumann, Topology::Hexagonal] {
+      for counting in [Counting::Scan, Counting::Tables] {
+        let shaders = rules.compile(topology, counting, CellLayout::Wide).unwrap();
+        let mut runner = Runner::new(
+          64,
+          topology,
+          &terrain,
+          seasons.clone(),
+          shaders,
+          Residency::Auto,
+          0,
+        )
+        .unwrap();
+        runner.verify_rules(&rules, &terrain, 20).unwrap();
+      }
+    }
+  }
+}
@@ -0,0 +1,648 @@
+use super::{
+  Aggregate, AssignOp, BinOp, Block, Branch, Builtin, Expr, Field, Neighbours, RuleSet, Stmt,
+};
+use crate::game::Concept;
+use crate::game::Terrain;
+use crate::game::Topology;
+
+#[derive(Clone, PartialEq)]
+enum Token {
+  Ident(String),
+  Int(i32),
+  Sym(&'static str),
+  End,
+}
+
+// Longest symbols first, so `<=` is not read as `<` followed by `=`.
+const SYMBOLS: [&str; 19] = [
+  "+=", "-=", "==", "!=", "<=", ">=", "{", "}", "(", ")", ",", "|", "*", "/", "+", "-", "<", ">",
+  "=",
+];
+
+struct Lexed {
+  token: Token,
+  line: usize,
+}
+
+fn lex(source: &str) -> Result<Vec<Lexed>, String> {
+  let mut tokens = Vec::new();
+  for (number, line) in source.lines().enumerate() {
+    let line_number = number + 1;
+    let line = match line.find('#') {
+      Some(comment) => &line[..comment],
+      None => line,
+    };
+    let bytes = line.as_bytes();
+    let mut pos = 0;
+    'next: while pos < bytes.len() {
+      let c = bytes[pos] as char;
+      if c.is_whitespace() {
+        pos += 1;
+      } else if c.is_ascii_alphabetic() || c == '_' {
+        let start = pos;
+        while pos < bytes.len() && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_') {
+          pos += 1;
+        }
+        tokens.push(Lexed {
+          token: Token::Ident(line[start..pos].to_string()),
+          line: line_number,
+        });
+      } else if c.is_ascii_digit() {
+        let start = pos;
+        while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+          pos += 1;
+        }
+        let value = line[start..pos].parse::<i32>().map_err(|_| {
+          format!(
+            "line {}: number {} is too large",
+            line_number,
+            &line[start..pos]
+          )
+        })?;
+        tokens.push(Lexed {
+          token: Token::Int(value),
+          line: line_number,
+        });
+      } else {
+        for symbol in SYMBOLS.iter() {
+          if line[pos..].starts_with(symbol) {
+            tokens.push(Lexed {
+              token: Token::Sym(symbol),
+              line: line_number,
+            });
+            pos += symbol.len();
+            continue 'next;
+          }
+        }
+        return Err(format!(
+          "line {}: unexpected character '{}'",
+          line_number, c
+        ));
+      }
+    }
+  }
+  tokens.push(Lexed {
+    token: Token::End,
+    line: source.lines().count(),
+  });
+  Ok(tokens)
+}
+
+fn constant_named(name: &str) -> Option<i32> {
+  if let Some(concept) = Concept::named(name) {
+    return Some(concept as i32);
+  }
+  match name {
+    "Plain" => Some(Terrain::Plain as i32),
+    "Meadow" => Some(Terrain::Meadow as i32),
+    "Rock" => Some(Terrain::Rock as i32),
+    "Water" => Some(Terrain::Water as i32),
+    "Moore" => Some(Topology::Moore as i32),
+    "VonNeumann" => Some(Topology::VonNeumann as i32),
+    "Hexagonal" => Some(Topology::Hexagonal as i32),
+    _ => None,
+  }
+}
+
+fn builtin_named(name: &str) -> Option<Builtin> {
+  match name {
+    "concept" => Some(Builtin::Concept),
+    "blood" => Some(Builtin::Blood),
+    "joy" => Some(Builtin::Joy),
+    "terrain" => Some(Builtin::Terrain),
+    "topology" => Some(Builtin::Topology),
+    "tick" => Some(Builtin::Tick),
+    "x" => Some(Builtin::X),
+    "y" => Some(Builtin::Y),
+    "width" => Some(Builtin::Width),
+    "sprout" => Some(Builtin::Sprout),
+    "joy_gain" => Some(Builtin::JoyGain),
+    "dogwood_decay" => Some(Builtin::DogwoodDecay),
+    "birth" => Some(Builtin::Birth),
+    "survive_min" => Some(Builtin::SurviveMin),
+    "survive_max" => Some(Builtin::SurviveMax),
+    "crowd" => Some(Builtin::Crowd),
+    _ => None,
+  }
+}
+
+const KEYWORDS: [&str; 9] = ["on", "let", "if", "else", "dust", "and", "or", "not", "any"];
+
+struct Parser {
+  tokens: Vec<Lexed>,
+  pos: usize,
+  variables: Vec<String>,
+  scopes: Vec<Vec<(String, usize)>>,
+  scans: Vec<Neighbours>,
+  branches: Vec<Branch>,
+}
+
+impl Parser {
+  fn peek(&self) -> &Token {
+    &self.tokens[self.pos].token
+  }
+
+  fn line(&self) -> usize {
+    self.tokens[self.pos].line
+  }
+
+  fn error<T>(&self, message: &str) -> Result<T, String> {
+    Err(format!("line {}: {}", self.line(), message))
+  }
+
+  fn next(&mut self) -> Token {
+    let token = self.tokens[self.pos].token.clone();
+    if token != Token::End {
+      self.pos += 1;
+    }
+    token
+  }
+
+  fn eat(&mut self, symbol: &str) -> bool {
+    if let Token::Sym(s) = self.peek() {
+      if *s == symbol {
+        self.pos += 1;
+        return true;
+      }
+    }
+    false
+  }
+
+  fn eat_word(&mut self, word: &str) -> bool {
+    if let Token::Ident(s) = self.peek() {
+      if s == word {
+        self.pos += 1;
+        return true;
+      }
+    }
+    false
+  }
+
+  fn expect(&mut self, symbol: &str) -> Result<(), String> {
+    if self.eat(symbol) {
+      Ok(())
+    } else {
+      self.error(&format!("expected '{}'", symbol))
+    }
+  }
+
+  fn ident(&mut self) -> Result<String, String> {
+    match self.next() {
+      Token::Ident(name) => Ok(name),
+      _ => {
+        self.pos -= 1;
+        self.error("expected a name")
+      }
+    }
+  }
+
+  fn concept(&mut self) -> Result<Concept, String> {
+    let name = self.ident()?;
+    match Concept::named(&name) {
+      Some(concept) => Ok(concept),
+      None => self.error(&format!("unknown concept {}", name)),
+    }
+  }
+
+  fn radius(&mut self) -> Result<i32, String> {
+    match self.next() {
+      Token::Int(value) => Ok(value),
+      _ => {
+        self.pos -= 1;
+        self.error("expected a radius")
+      }
+    }
+  }
+
+  /// `outer, inner)` closing every neighbourhood call.
+  fn radii(&mut self) -> Result<(i32, i32), String> {
+    let outer = self.radius()?;
+    self.expect(",")?;
+    let inner = self.radius()?;
+    self.expect(")")?;
+    if inner > outer {
+      return self.error("inner radius is larger than the outer radius");
+    }
+    Ok((outer, inner))
+  }
+
+  fn lookup(&self, name: &str) -> Option<usize> {
+    self
+      .scopes
+      .iter()
+      .rev()
+      .flat_map(|scope| scope.iter().rev())
+      .find(|(n, _)| n == name)
+      .map(|(_, slot)| *slot)
+  }
+
+  fn file(&mut self) -> Result<Vec<Block>, String> {
+    let mut blocks: Vec<Block> = Vec::new();
+    while *self.peek() != Token::End {
+      if !self.eat_word("on") {
+        return self.error("expected 'on'");
+      }
+      let mut concepts = vec![self.concept()?];
+      while self.eat(",") {
+        concepts.push(self.concept()?);
+      }
+      for concept in concepts.iter() {
+        if blocks.iter().any(|b| b.concepts.contains(concept)) {
+          return self.error("a concept can only have one 'on' block");
+        }
+      }
+      let body = self.block()?;
+      blocks.push(Block { concepts, body });
+    }
+    Ok(blocks)
+  }
+
+  fn block(&mut self) -> Result<Vec<Stmt>, String> {
+    self.expect("{")?;
+    self.scopes.push(Vec::new());
+    let mut body = Vec::new();
+    while !self.eat("}") {
+      if *self.peek() == Token::End {
+        return self.error("unclosed block");
+      }
+      body.push(self.statement()?);
+    }
+    self.scopes.pop();
+    Ok(body)
+  }
+
+  /// The block of an `if` arm, starting with its `Stmt::Branch`.
+  fn arm(&mut self, arm: &'static str) -> Result<Vec<Stmt>, String> {
+    let id = self.branches.len();
+    self.branches.push(Branch {
+      line: self.line(),
+      arm,
+    });
+    let mut body = vec![Stmt::Branch(id)];
+    body.extend(self.block()?);
+    Ok(body)
+  }
+
+  fn statement(&mut self) -> Result<Stmt, String> {
+    if self.eat_word("let") {
+      let name = self.ident()?;
+      if KEYWORDS.contains(&name.as_str())
+        || builtin_named(&name).is_some()
+        || constant_named(&name).is_some()
+      {
+        return self.error(&format!("{} is reserved", name));
+      }
+      if self.scopes.last().unwrap().iter().any(|(n, _)| *n == name) {
+        return self.error(&format!("{} is already defined in this block", name));
+      }
+      self.expect("=")?;
+      let value = self.expr()?;
+      let slot = self.variables.len();
+      self.variables.push(name.clone());
+      self.scopes.last_mut().unwrap().push((name, slot));
+      return Ok(Stmt::Let(slot, value));
+    }
+
+    if self.eat_word("dust") {
+      return Ok(Stmt::Dust);
+    }
+
+    if self.eat_word("if") {
+      let condition = self.expr()?;
+      let mut branches = vec![(condition, self.arm("if")?)];
+      let mut otherwise = None;
+      while self.eat_word("else") {
+        if self.eat_word("if") {
+          let condition = self.expr()?;
+          branches.push((condition, self.arm("else if")?));
+        } else {
+          otherwise = Some(self.arm("else")?);
+          break;
+        }
+      }
+      return Ok(Stmt::If(branches, otherwise));
+    }
+
+    let target = self.ident()?;
+    if target == "concept" {
+      self.expect("=")?;
+      return Ok(Stmt::SetConcept(self.concept()?));
+    }
+    let field = match target.as_str() {
+      "blood" => Field::Blood,
+      "joy" => Field::Joy,
+      _ => return self.error(&format!("cannot assign to {}", target)),
+    };
+    let op = if self.eat("=") {
+      AssignOp::Set
+    } else if self.eat("+=") {
+      AssignOp::Add
+    } else if self.eat("-=") {
+      AssignOp::Sub
+    } else {
+      return self.error("expected '=', '+=' or '-='");
+    };
+    Ok(Stmt::Assign(field, op, self.expr()?))
+  }
+
+  fn expr(&mut self) -> Result<Expr, String> {
+    let mut left = self.and()?;
+    while self.eat_word("or") {
+      left = Expr::Binary(BinOp::Or, Box::new(left), Box::new(self.and()?));
+    }
+    Ok(left)
+  }
+
+  fn and(&mut self) -> Result<Expr, String> {
+    let mut left = self.not()?;
+    while self.eat_word("and") {
+      left = Expr::Binary(BinOp::And, Box::new(left), Box::new(self.not()?));
+    }
+    Ok(left)
+  }
+
+  fn not(&mut self) -> Result<Expr, String> {
+    if self.eat_word("not") {
+      return Ok(Expr::Not(Box::new(self.not()?)));
+    }
+    self.comparison()
+  }
+
+  fn comparison(&mut self) -> Result<Expr, String> {
+    let left = self.sum()?;
+    let op = match self.peek() {
+      Token::Sym("==") => BinOp::Eq,
+      Token::Sym("!=") => BinOp::Ne,
+      Token::Sym("<") => BinOp::Lt,
+      Token::Sym("<=") => BinOp::Le,
+      Token::Sym(">") => BinOp::Gt,
+      Token::Sym(">=") => BinOp::Ge,
+      _ => return Ok(left),
+    };
+    self.pos += 1;
+    Ok(Expr::Binary(op, Box::new(left), Box::new(self.sum()?)))
+  }
+
+  fn sum(&mut self) -> Result<Expr, String> {
+    let mut left = self.term()?;
+    loop {
+      let op = match self.peek() {
+        Token::Sym("+") => BinOp::Add,
+        Token::Sym("-") => BinOp::Sub,
+        _ => return Ok(left),
+      };
+      self.pos += 1;
+      left = Expr::Binary(op, Box::new(left), Box::new(self.term()?));
+    }
+  }
+
+  fn term(&mut self) -> Result<Expr, String> {
+    let mut left = self.unary()?;
+    loop {
+      let op = match self.peek() {
+        Token::Sym("*") => BinOp::Mul,
+        Token::Sym("/") => BinOp::Div,
+        _ => return Ok(left),
+      };
+      self.pos += 1;
+      left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+    }
+  }
+
+  fn unary(&mut self) -> Result<Expr, String> {
+    if self.eat("-") {
+      return Ok(Expr::Neg(Box::new(self.unary()?)));
+    }
+    self.atom()
+  }
+
+  /// `Concept | Concept ...` or `*` for every concept.
+  fn concepts(&mut self) -> Result<Vec<Concept>, String> {
+    if self.eat("*") {
+      return Ok(Vec::new());
+    }
+    let mut concepts = vec![self.concept()?];
+    while self.eat("|") {
+      concepts.push(self.concept()?);
+    }
+    Ok(concepts)
+  }
+
+  fn scan(&mut self, aggregate: Aggregate) -> Result<Expr, String> {
+    let concepts = self.concepts()?;
+    self.expect(",")?;
+    let (outer, inner) = self.radii()?;
+    let scan = Neighbours {
+      id: self.scans.len(),
+      aggregate,
+      concepts,
+      outer,
+      inner,
+    };
+    self.scans.push(scan.clone());
+    Ok(Expr::Neighbours(scan))
+  }
+
+  fn two_args(&mut self) -> Result<(Box<Expr>, Box<Expr>), String> {
+    let a = self.expr()?;
+    self.expect(",")?;
+    let b = self.expr()?;
+    self.expect(")")?;
+    Ok((Box::new(a), Box::new(b)))
+  }
+
+  fn atom(&mut self) -> Result<Expr, String> {
+    match self.next() {
+      Token::Int(value) => Ok(Expr::Int(value)),
+      Token::Sym("(") => {
+        let inner = self.expr()?;
+        self.expect(")")?;
+        Ok(inner)
+      }
+      Token::Ident(name) => {
+        if self.eat("(") {
+          return self.call(&name);
+        }
+        if let Some(slot) = self.lookup(&name) {
+          return Ok(Expr::Var(slot));
+        }
+        if let Some(builtin) = builtin_named(&name) {
+          return Ok(Expr::Builtin(builtin));
+        }
+        if let Some(value) = constant_named(&name) {
+          return Ok(Expr::Int(value));
+        }
+        self.pos -= 1;
+        self.error(&format!("unknown name {}", name))
+      }
+      _ => {
+        self.pos -= 1;
+        self.error("expected a value")
+      }
+    }
+  }
+
+  fn call(&mut self, name: &str) -> Result<Expr, String> {
+    match name {
+      "count" => self.scan(Aggregate::Count),
+      "any" => self.scan(Aggregate::Any),
+      "sum" => {
+        let field = match self.ident()?.as_str() {
+          "blood" => Field::Blood,
+          "joy" => Field::Joy,
+          _ => return self.error("sum() needs blood or joy"),
+        };
+        self.expect(",")?;
+        self.scan(Aggregate::Sum(field))
+      }
+      "cells" => {
+        let (outer, inner) = self.radii()?;
+        Ok(Expr::Cells(outer, inner))
+      }
+      "scaled" => {
+        let threshold = self.expr()?;
+        self.expect(",")?;
+        let (outer, inner) = self.radii()?;
+        Ok(Expr::Scaled(Box::new(threshold), outer, inner))
+      }
+      "min" => {
+        let (a, b) = self.two_args()?;
+        Ok(Expr::Min(a, b))
+      }
+      "max" => {
+        let (a, b) = self.two_args()?;
+        Ok(Expr::Max(a, b))
+      }
+      "abs" => {
+        let value = self.expr()?;
+        self.expect(")")?;
+        Ok(Expr::Abs(Box::new(value)))
+      }
+      _ => self.error(&format!("unknown function {}", name)),
+    }
+  }
+}
+
+pub fn parse(source: &str) -> Result<RuleSet, String> {
+  let mut parser = Parser {
+    tokens: lex(source)?,
+    pos: 0,
+    variables: Vec::new(),
+    scopes: Vec::new(),
+    scans: Vec::new(),
+    branches: Vec::new(),
+  };
+  let blocks = parser.file()?;
+
+  Ok(RuleSet {
+    blocks,
+    variables: parser.variables,
+    scans: parser.scans,
+    branches: parser.branches,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn first_value(rules: &RuleSet) -> &Expr {
+    match &rules.blocks[0].body[0] {
+      Stmt::Assign(_, _, value) | Stmt::Let(_, value) => value,
+      _ => panic!("expected an assignment"),
+    }
+  }
+
+  #[test]
+  fn parses_the_garden() {
+    let rules = parse(crate::rules::GARDEN_RULES).unwrap();
+    assert_eq!(rules.blocks.len(), 3);
+    assert!(rules.blocks[0].concepts == [Concept::Soil, Concept::Sunflower]);
+    assert_eq!(
+      rules.variables,
+      vec!["count", "revelry", "barren", "law", "order"]
+    );
+    assert_eq!(rules.scans.len(), 7);
+    assert_eq!(rules.branches[0].to_string(), "line 9: if");
+  }
+
+  #[test]
+  fn products_bind_tighter_than_sums() {
+    let rules = parse("on Soil { blood = 1 + 2 * 3 }").unwrap();
+    match first_value(&rules) {
+      Expr::Binary(BinOp::Add, a, b) => {
+        assert!(matches!(**a, Expr::Int(1)));
+        assert!(matches!(**b, Expr::Binary(BinOp::Mul, _, _)));
+      }
+      _ => panic!("expected a sum"),
+    }
+  }
+
+  #[test]
+  fn and_binds_tighter_than_or() {
+    let rules = parse("on Soil { blood = 1 or 0 and not 1 }").unwrap();
+    match first_value(&rules) {
+      Expr::Binary(BinOp::Or, _, b) => match &**b {
+        Expr::Binary(BinOp::And, _, c) => assert!(matches!(**c, Expr::Not(_))),
+        _ => panic!("expected a conjunction"),
+      },
+      _ => panic!("expected a disjunction"),
+    }
+  }
+
+  #[test]
+  fn names_resolve_to_variables_builtins_and_constants() {
+    let rules = parse("on Soil { let a = Rose\n blood = a + joy }").unwrap();
+    assert!(matches!(first_value(&rules), Expr::Int(2)));
+    match &rules.blocks[0].body[1] {
+      Stmt::Assign(Field::Blood, AssignOp::Set, Expr::Binary(BinOp::Add, a, b)) => {
+        assert!(matches!(**a, Expr::Var(0)));
+        assert!(matches!(**b, Expr::Builtin(Builtin::Joy)));
+      }
+      _ => panic!("expected an assignment to blood"),
+    }
+  }
+
+  #[test]
+  fn scans_are_numbered() {
+    let rules = parse("on Rose { blood = count(Rose | Elder, 2, 1) + sum(joy, *, 1, 1) }").unwrap();
+    assert_eq!(rules.scans.len(), 2);
+    assert!(rules.scans[0].concepts == [Concept::Rose, Concept::Elder]);
+    assert!(rules.scans[0].aggregate == Aggregate::Count);
+    assert!(rules.scans[1].concepts.is_empty());
+    assert!(rules.scans[1].aggregate == Aggregate::Sum(Field::Joy));
+  }
+
+  #[test]
+  fn every_arm_is_a_branch() {
+    let rules = parse("on Soil {\n if 1 { dust }\n else if 0 { dust }\n else { dust }\n}").unwrap();
+    let arms: Vec<String> = rules.branches.iter().map(|b| b.to_string()).collect();
+    assert_eq!(arms, vec!["line 2: if", "line 3: else if", "line 4: else"]);
+  }
+
+  #[test]
+  fn comments_are_skipped() {
+    assert!(parse("# nothing yet\non Soil { # still nothing\n}").is_ok());
+  }
+
+  #[test]
+  fn variables_end_with_their_block() {
+    let error = parse("on Soil {\n if 1 { let a = 1 }\n blood = a\n}")
+      .err()
+      .unwrap();
+    assert_eq!(error, "line 3: unknown name a");
+  }
+
+  #[test]
+  fn rejects_bad_rules() {
+    let error = |source: &str| parse(source).err().unwrap();
+    assert!(error("on Soil { let joy = 1 }").contains("joy is reserved"));
+    assert!(error("on Soil { let a = 1\n let a = 2 }").contains("already defined"));
+    assert!(error("on Soil { }\non Soil, Rose { }").contains("only have one 'on' block"));
+    assert!(error("on Soil { dust").contains("unclosed block"));
+    assert!(error("on Soil { blood = 2147483648 }").contains("too large"));
+    assert!(error("on Soil { blood = 1 ; }").contains("unexpected character ';'"));
+    assert!(error("on Soil { x = 1 }").contains("cannot assign to x"));
+    assert!(error("on Soil { blood = sqrt(4) }").contains("unknown function sqrt"));
+  }
+}
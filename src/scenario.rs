@@ -0,0 +1,483 @@
+//! Scripted gameplay checks, run headless with `scenario=<path>`.
+//!
+//! A scenario is a plain-text file, one entry per line, with `#` comments:
+//!
+//! ```text
+//! size 64
+//! seed 7
+//! world soil
+//!
+//! at 0 fill Sunflower 10 10 3 3
+//! at 0 place Rose 30 30
+//! at 50 set decay 2
+//! at 80 pause 10
+//! at 200 expect Elder >= 1
+//! at 200 expect Rose < 50
+//! ```
+//!
+//! `size`, `seed` and `world` (`soil`, `sunflowers` or `grove`) replace the
+//! command line's. Every `at <tick>` entry runs once that many ticks have run,
+//! in file order:
+//!
+//! - `place <Concept> <x> <y>` and `fill <Concept> <x> <y> <w> <h>` turn cells
+//!   into the concept, keeping their blood and joy, before the next tick.
+//!   Fills are clipped to the world.
+//! - `pause <ticks>` holds the garden for as many steps of the runner.
+//! - `set <rule> <value>` overrides a season rule (`sprout`, `joy`, `decay`).
+//! - `expect <Concept> <op> <count>` checks the number of cells of a concept,
+//!   with `op` one of `<`, `<=`, `>`, `>=`, `==` and `!=`.
+
+use crate::args::Args;
+use crate::bench::Seeding;
+use crate::game::CellState;
+use crate::game::Concept;
+use crate::game::Runner;
+use crate::rules::RuleSet;
+use crate::seasons::set_rule;
+use crate::seasons::SeasonRules;
+use crate::terrain;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Comparison {
+  Less,
+  LessOrEqual,
+  Greater,
+  GreaterOrEqual,
+  Equal,
+  NotEqual,
+}
+
+impl Comparison {
+  fn parse(symbol: &str) -> Option<Comparison> {
+    match symbol {
+      "<" => Some(Comparison::Less),
+      "<=" => Some(Comparison::LessOrEqual),
+      ">" => Some(Comparison::Greater),
+      ">=" => Some(Comparison::GreaterOrEqual),
+      "==" => Some(Comparison::Equal),
+      "!=" => Some(Comparison::NotEqual),
+      _ => None,
+    }
+  }
+
+  fn holds(self, actual: usize, expected: usize) -> bool {
+    match self {
+      Comparison::Less => actual < expected,
+      Comparison::LessOrEqual => actual <= expected,
+      Comparison::Greater => actual > expected,
+      Comparison::GreaterOrEqual => actual >= expected,
+      Comparison::Equal => actual == expected,
+      Comparison::NotEqual => actual != expected,
+    }
+  }
+}
+
+enum Action {
+  /// Turn the `w` by `h` cells from (`x`, `y`) into `concept`.
+  Fill {
+    concept: Concept,
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+  },
+  Pause(u32),
+  Set(String, i32),
+  Expect {
+    concept: Concept,
+    comparison: Comparison,
+    count: usize,
+  },
+}
+
+struct Entry {
+  tick: u64,
+  action: Action,
+  /// Source line, for reports.
+  line: usize,
+  text: String,
+}
+
+/// What a scenario plays on: the runner, or the CPU evaluator in tests.
+trait Simulation {
+  fn execute(&mut self);
+  fn toggle_pause(&mut self);
+  fn snapshot(&mut self) -> Vec<CellState>;
+  fn load(&mut self, cells: &[CellState]);
+  fn override_rule(&mut self, key: &str, value: i32) -> Result<(), String>;
+}
+
+impl Simulation for Runner {
+  fn execute(&mut self) {
+    Runner::execute(self)
+  }
+
+  fn toggle_pause(&mut self) {
+    Runner::toggle_pause(self)
+  }
+
+  fn snapshot(&mut self) -> Vec<CellState> {
+    Runner::snapshot(self)
+  }
+
+  fn load(&mut self, cells: &[CellState]) {
+    Runner::load(self, cells)
+  }
+
+  fn override_rule(&mut self, key: &str, value: i32) -> Result<(), String> {
+    Runner::override_rule(self, key, value)
+  }
+}
+
+pub struct Scenario {
+  size: Option<u32>,
+  seed: Option<u64>,
+  world: Seeding,
+  /// Sorted by tick, in file order within a tick.
+  entries: Vec<Entry>,
+}
+
+fn number<T: std::str::FromStr>(word: Option<&str>, what: &str) -> Result<T, String> {
+  let word = word.ok_or(format!("expected {}", what))?;
+  word
+    .parse::<T>()
+    .map_err(|_| format!("expected {}, found {}", what, word))
+}
+
+fn concept(word: Option<&str>) -> Result<Concept, String> {
+  let word = word.ok_or("expected a concept")?;
+  Concept::named(word).ok_or(format!("unknown concept {}", word))
+}
+
+fn action(words: &mut std::str::SplitWhitespace) -> Result<Action, String> {
+  let action = match words.next() {
+    Some("place") => Action::Fill {
+      concept: concept(words.next())?,
+      x: number(words.next(), "x")?,
+      y: number(words.next(), "y")?,
+      w: 1,
+      h: 1,
+    },
+    Some("fill") => Action::Fill {
+      concept: concept(words.next())?,
+      x: number(words.next(), "x")?,
+      y: number(words.next(), "y")?,
+      w: number(words.next(), "a width")?,
+      h: number(words.next(), "a height")?,
+    },
+    Some("pause") => Action::Pause(number(words.next(), "a number of ticks")?),
+    Some("set") => {
+      let key = words.next().ok_or("expected a rule")?;
+      let value = number(words.next(), "a value")?;
+      set_rule(&mut SeasonRules::default(), key, value)?;
+      Action::Set(key.to_string(), value)
+    }
+    Some("expect") => {
+      let concept = concept(words.next())?;
+      let symbol = words.next().ok_or("expected a comparison")?;
+      Action::Expect {
+        concept,
+        comparison: Comparison::parse(symbol).ok_or(format!("unknown comparison {}", symbol))?,
+        count: number(words.next(), "a count")?,
+      }
+    }
+    Some(other) => return Err(format!("unknown action {}", other)),
+    None => return Err("expected an action".to_string()),
+  };
+  Ok(action)
+}
+
+impl Scenario {
+  pub fn parse(source: &str) -> Result<Scenario, String> {
+    let mut scenario = Scenario {
+      size: None,
+      seed: None,
+      world: Seeding::Soil,
+      entries: Vec::new(),
+    };
+
+    for (index, line) in source.lines().enumerate() {
+      let line_number = index + 1;
+      let text = match line.find('#') {
+        Some(comment) => &line[..comment],
+        None => line,
+      }
+      .trim();
+      let mut words = text.split_whitespace();
+      let parsed: Result<(), String> = match words.next() {
+        None => continue,
+        Some("size") => number(words.next(), "a size").map(|size| scenario.size = Some(size)),
+        Some("seed") => number(words.next(), "a seed").map(|seed| scenario.seed = Some(seed)),
+        Some("world") => {
+          let name = words.next().unwrap_or("");
+          Seeding::parse(name)
+            .map(|world| scenario.world = world)
+            .ok_or(format!("unknown world {}", name))
+        }
+        Some("at") => number(words.next(), "a tick").and_then(|tick| {
+          scenario.entries.push(Entry {
+            tick,
+            action: action(&mut words)?,
+            line: line_number,
+            text: text.to_string(),
+          });
+          Ok(())
+        }),
+        Some(other) => Err(format!("unknown entry {}", other)),
+      };
+      parsed
+        .and_then(|_| match words.next() {
+          Some(extra) => Err(format!("unexpected {}", extra)),
+          None => Ok(()),
+        })
+        .map_err(|e| format!("line {}: {}", line_number, e))?;
+    }
+
+    scenario.entries.sort_by_key(|entry| entry.tick);
+    Ok(scenario)
+  }
+
+  /// Run the scenario on the GPU, reporting each assertion. True if all held.
+  pub fn run(&self, args: &Args, rules: &RuleSet) -> bool {
+    let size = self.size.unwrap_or(args.size);
+    let seed = self.seed.unwrap_or(args.seed);
+    let terrain = terrain::generate(&args.terrain, size, seed).expect("failed to load terrain");
+    let shaders = rules
+      .compile(args.topology, args.counting, args.cell_layout)
+      .unwrap_or_else(|e| panic!("Could not compile rules: {}", e));
+    let mut runner = Runner::new(
+      size,
+      args.topology,
+      &terrain,
+      args.seasons.clone(),
+      shaders,
+      args.memory,
+      args.readback_rate,
+    )
+    .unwrap_or_else(|e| panic!("Could not start the runner: {}", e));
+    runner.load(&self.world.world(size, seed));
+    self.play(&mut runner, size)
+  }
+
+  /// Play the entries on `runner`, a world `size` cells wide seeded as the
+  /// scenario says, reporting each assertion. True if all held.
+  fn play(&self, runner: &mut impl Simulation, size: u32) -> bool {
+    let mut failures = 0;
+    let mut tick = 0;
+    let mut entries = self.entries.iter().peekable();
+    while let Some(next) = entries.peek() {
+      while tick < next.tick {
+        runner.execute();
+        tick += 1;
+      }
+
+      // Everything due now works on one copy of the cells, loaded back only
+      // if a mutation changed it.
+      let mut cells: Option<Vec<CellState>> = None;
+      let mut mutated = false;
+      while let Some(entry) = entries.next_if(|entry| entry.tick == tick) {
+        match &entry.action {
+          Action::Fill {
+            concept,
+            x,
+            y,
+            w,
+            h,
+          } => {
+            let cells = cells.get_or_insert_with(|| runner.snapshot());
+            for cell_y in *y..y.saturating_add(*h).min(size) {
+              for cell_x in *x..x.saturating_add(*w).min(size) {
+                cells[(cell_x + cell_y * size) as usize].concept = *concept;
+              }
+            }
+            mutated = true;
+          }
+          Action::Pause(ticks) => {
+            if mutated {
+              runner.load(cells.as_ref().unwrap());
+              mutated = false;
+            }
+            runner.toggle_pause();
+            for _ in 0..*ticks {
+              runner.execute();
+            }
+            runner.toggle_pause();
+          }
+          Action::Set(key, value) => {
+            runner
+              .override_rule(key, *value)
+              .expect("rules are checked when parsing");
+          }
+          Action::Expect {
+            concept,
+            comparison,
+            count,
+          } => {
+            let cells = cells.get_or_insert_with(|| runner.snapshot());
+            let actual = cells.iter().filter(|cell| cell.concept == *concept).count();
+            let held = comparison.holds(actual, *count);
+            if !held {
+              failures += 1;
+            }
+            println!(
+              "{} line {}: {} (found {})",
+              if held { "PASS" } else { "FAIL" },
+              entry.line,
+              entry.text,
+              actual
+            );
+          }
+        }
+      }
+      if mutated {
+        runner.load(cells.as_ref().unwrap());
+      }
+    }
+
+    println!(
+      "{} after {} ticks",
+      if failures == 0 {
+        "scenario passed".to_string()
+      } else {
+        format!("{} assertions failed", failures)
+      },
+      tick
+    );
+    failures == 0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::game::{CellLayout, Terrain, Topology};
+  use crate::rules::World;
+
+  /// Sunflowers blooming into Roses, which Elders dust.
+  const RULES: &str = "
+on Sunflower {
+  joy += joy_gain
+  if joy >= 4 {
+    concept = Rose
+  }
+}
+on Rose {
+  if any(Elder, 1, 1) {
+    dust
+  }
+}
+";
+
+  /// A Moore world of plain terrain stepped on the CPU.
+  struct Cpu {
+    rules: RuleSet,
+    cells: Vec<CellState>,
+    width: u32,
+    season: SeasonRules,
+    tick: u32,
+    paused: bool,
+  }
+
+  impl Simulation for Cpu {
+    fn execute(&mut self) {
+      if self.paused {
+        return;
+      }
+      let world = World {
+        cells: &self.cells,
+        terrain: &vec![Terrain::Plain; self.cells.len()],
+        width: self.width,
+        topology: Topology::Moore,
+        season: self.season,
+        tick: self.tick,
+        layout: CellLayout::Wide,
+      };
+      self.cells = self.rules.step(&world, &[]);
+      self.tick += 1;
+    }
+
+    fn toggle_pause(&mut self) {
+      self.paused = !self.paused;
+    }
+
+    fn snapshot(&mut self) -> Vec<CellState> {
+      self.cells.clone()
+    }
+
+    fn load(&mut self, cells: &[CellState]) {
+      self.cells = cells.to_vec();
+    }
+
+    fn override_rule(&mut self, key: &str, value: i32) -> Result<(), String> {
+      set_rule(&mut self.season, key, value)
+    }
+  }
+
+  /// Play `source` on the CPU, true if every assertion held.
+  fn play(source: &str) -> bool {
+    let scenario = Scenario::parse(source).unwrap();
+    let size = scenario.size.unwrap();
+    let mut cpu = Cpu {
+      rules: RuleSet::parse(RULES).unwrap(),
+      cells: scenario.world.world(size, scenario.seed.unwrap_or(0)),
+      width: size,
+      season: SeasonRules::default(),
+      tick: 0,
+      paused: false,
+    };
+    scenario.play(&mut cpu, size)
+  }
+
+  const BLOOM: &str = "
+size 8
+world soil
+
+at 0 fill Sunflower 0 0 2 2
+at 0 set joy 2
+at 1 expect Sunflower == 4
+at 2 expect Rose == 4
+at 2 place Elder 2 2  # next to the Rose at 1 1
+at 2 pause 5
+at 2 expect Rose >= 4
+at 3 expect Rose == 3
+at 3 expect Soil == 60
+";
+
+  #[test]
+  fn scripted_scenarios_pass() {
+    assert!(play(BLOOM));
+  }
+
+  #[test]
+  fn failed_assertions_fail_the_scenario() {
+    assert!(!play(&format!("{}at 3 expect Elder != 1\n", BLOOM)));
+    assert!(!play(&format!("{}at 0 expect Sunflower < 4\n", BLOOM)));
+  }
+
+  #[test]
+  fn entries_run_in_tick_then_file_order() {
+    let scenario = Scenario::parse(BLOOM).unwrap();
+    let lines: Vec<usize> = scenario.entries.iter().map(|entry| entry.line).collect();
+    assert_eq!(lines, vec![5, 6, 7, 8, 9, 10, 11, 12, 13]);
+    let scenario = Scenario::parse("at 9 pause 1\nat 2 pause 1\nat 9 pause 2").unwrap();
+    let lines: Vec<usize> = scenario.entries.iter().map(|entry| entry.line).collect();
+    assert_eq!(lines, vec![2, 1, 3]);
+  }
+
+  #[test]
+  fn rejects_bad_entries() {
+    for (source, error) in [
+      ("size", "line 1: expected a size"),
+      ("at 0 place Tulip 1 1", "line 1: unknown concept Tulip"),
+      ("at 0 set decay", "line 1: expected a value"),
+      ("at 0 set bloom 1", "line 1: Unknown season rule bloom"),
+      ("at 0 expect Rose ~ 1", "line 1: unknown comparison ~"),
+      ("\nat 0 pause 1 2", "line 2: unexpected 2"),
+      ("world desert", "line 1: unknown world desert"),
+    ]
+    .iter()
+    {
+      assert_eq!(Scenario::parse(source).err().unwrap(), *error);
+    }
+  }
+}
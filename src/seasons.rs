@@ -0,0 +1,119 @@
+/// Rule parameters that change with the seasons. Mirrored by `SeasonRules` in
+/// `src/rules/prelude.comp`.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct SeasonRules {
+  /// Whether Soil may come to Life as a Sunflower.
+  pub sprout: i32,
+  /// Joy a content Sunflower gains each tick.
+  pub joy_gain: i32,
+  /// Blood a Dogwood regains each tick, bringing it closer to dust.
+  pub dogwood_decay: i32,
+}
+
+impl Default for SeasonRules {
+  fn default() -> SeasonRules {
+    SeasonRules {
+      sprout: 1,
+      joy_gain: 1,
+      dogwood_decay: 1,
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct Season {
+  pub name: String,
+  pub rules: SeasonRules,
+}
+
+/// A year of `period` ticks, split evenly between the named seasons.
+/// A period of zero disables seasons and every tick uses the default rules.
+#[derive(Clone)]
+pub struct Seasons {
+  pub period: u64,
+  pub phase: u64,
+  pub seasons: Vec<Season>,
+}
+
+impl Seasons {
+  pub fn at(&self, tick: u64) -> Option<&Season> {
+    if self.period == 0 || self.seasons.is_empty() {
+      return None;
+    }
+
+    let offset = (tick.wrapping_add(self.phase) % self.period) as u128;
+    let index = (offset * self.seasons.len() as u128) / self.period as u128;
+    self.seasons.get(index as usize)
+  }
+
+  pub fn rules_at(&self, tick: u64) -> SeasonRules {
+    self.at(tick).map(|season| season.rules).unwrap_or_default()
+  }
+}
+
+pub fn default_seasons() -> Vec<Season> {
+  let season = |name: &str, sprout: i32, dogwood_decay: i32| Season {
+    name: name.to_string(),
+    rules: SeasonRules {
+      sprout,
+      dogwood_decay,
+      ..SeasonRules::default()
+    },
+  };
+
+  vec![
+    season("spring", 1, 1),
+    season("summer", 0, 1),
+    season("autumn", 0, 1),
+    season("winter", 0, 2),
+  ]
+}
+
+/// Set the rule a season spec calls `key`: `sprout`, `joy` or `decay`.
+pub fn set_rule(rules: &mut SeasonRules, key: &str, value: i32) -> Result<(), String> {
+  match key {
+    "sprout" => rules.sprout = value,
+    "joy" => rules.joy_gain = value,
+    "decay" => rules.dogwood_decay = value,
+    _ => return Err(format!("Unknown season rule {}", key)),
+  }
+  Ok(())
+}
+
+/// Parse a comma separated list of seasons, each a name followed by optional
+/// `:key=value` rule overrides, e.g. `spring:sprout=1,winter:sprout=0:decay=2`.
+pub fn parse_seasons(value: &str) -> Result<Vec<Season>, String> {
+  value
+    .split(',')
+    .map(|spec| {
+      let mut parts = spec.split(':');
+      let name = parts.next().unwrap_or("").trim();
+      if name.is_empty() {
+        return Err(format!("Missing season name in: {}", spec));
+      }
+
+      let mut rules = SeasonRules::default();
+      for setting in parts {
+        let (key, value) = match setting.find('=') {
+          Some(split) => (&setting[..split], &setting[split + 1..]),
+          None => {
+            return Err(format!(
+              "Expected key=value in season {}: {}",
+              name, setting
+            ))
+          }
+        };
+        let value = value
+          .parse::<i32>()
+          .map_err(|_| format!("Could not parse {} in season {}: {}", key, name, value))?;
+        set_rule(&mut rules, key, value).map_err(|e| format!("{} in season {}", e, name))?;
+      }
+
+      Ok(Season {
+        name: name.to_string(),
+        rules,
+      })
+    })
+    .collect()
+}
@@ -0,0 +1,198 @@
+//! Telling when the garden has stopped going anywhere, from the census the
+//! GPU takes of every tick, and what to do about it.
+
+use crate::game::CellState;
+use crate::game::Concept;
+use rocket::serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Longest cycle looked for unless told otherwise.
+pub const MAX_PERIOD: usize = 64;
+
+/// How a garden stopped changing.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Stagnation {
+  /// Nothing but Soil.
+  Extinct,
+  /// Every tick the same as the last.
+  Static,
+  /// Back to the same cells every `period` ticks.
+  Cycle(u32),
+}
+
+impl fmt::Display for Stagnation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Stagnation::Extinct => write!(f, "extinct"),
+      Stagnation::Static => write!(f, "static"),
+      Stagnation::Cycle(period) => write!(f, "cycling every {} ticks", period),
+    }
+  }
+}
+
+/// A change in how the garden stagnates, as `/garden/stream` sends it.
+#[derive(Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Change {
+  /// Ticks run when the change was noticed.
+  pub tick: u64,
+  /// How the garden stopped changing, none once it changes again.
+  pub stagnation: Option<String>,
+}
+
+/// What the runner does to a garden stagnant for long enough.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Reseed {
+  /// Start over from Soil with Sunflowers on a tenth of the cells.
+  World,
+  /// Sow Sunflowers on a tenth of the Soil, leaving everything else be.
+  Sunflowers,
+}
+
+impl Reseed {
+  pub fn parse(name: &str) -> Option<Reseed> {
+    match name {
+      "world" => Some(Reseed::World),
+      "sunflowers" => Some(Reseed::Sunflowers),
+      _ => None,
+    }
+  }
+
+  pub fn apply(self, cells: &mut [CellState]) {
+    for cell in cells.iter_mut() {
+      if self == Reseed::World {
+        *cell = CellState {
+          concept: Concept::Soil,
+          blood: 0,
+          joy: 0,
+        };
+      }
+      if cell.concept == Concept::Soil && rand::random::<u8>() < 26 {
+        cell.concept = Concept::Sunflower;
+      }
+    }
+  }
+}
+
+/// What the census pass found in one tick.
+#[derive(Clone, Copy)]
+pub struct Census {
+  /// Hash of every cell and its position.
+  pub hash: u64,
+  /// Cells that are not Soil.
+  pub living: u64,
+}
+
+/// Remembers the hashes of recent ticks to notice the garden repeating.
+pub struct Watch {
+  /// Longest cycle looked for. Period 1 is a static garden.
+  max_period: usize,
+  /// Latest last.
+  recent: VecDeque<u64>,
+  /// The stagnation going on, with the tick it was noticed.
+  current: Option<(Stagnation, u64)>,
+}
+
+impl Watch {
+  pub fn new(max_period: usize) -> Watch {
+    Watch {
+      max_period,
+      recent: VecDeque::with_capacity(max_period),
+      current: None,
+    }
+  }
+
+  /// Take in the census of the garden after `tick` ticks. The change if the
+  /// garden just stagnated, changed how or started moving again.
+  pub fn observe(&mut self, tick: u64, census: Census) -> Option<Change> {
+    let stagnation = if census.living == 0 {
+      Some(Stagnation::Extinct)
+    } else {
+      self
+        .recent
+        .iter()
+        .rev()
+        .position(|hash| *hash == census.hash)
+        .map(|ago| match ago {
+          0 => Stagnation::Static,
+          _ => Stagnation::Cycle(ago as u32 + 1),
+        })
+    };
+
+    if self.max_period > 0 {
+      if self.recent.len() == self.max_period {
+        self.recent.pop_front();
+      }
+      self.recent.push_back(census.hash);
+    }
+
+    if stagnation == self.current.map(|(stagnation, _)| stagnation) {
+      return None;
+    }
+    self.current = stagnation.map(|stagnation| (stagnation, tick));
+    Some(Change {
+      tick,
+      stagnation: stagnation.map(|stagnation| stagnation.to_string()),
+    })
+  }
+
+  pub fn current(&self) -> Option<(Stagnation, u64)> {
+    self.current
+  }
+
+  /// Forget everything, e.g. once the garden was replaced after `tick`
+  /// ticks. The change if it was stagnant.
+  pub fn clear(&mut self, tick: u64) -> Option<Change> {
+    self.recent.clear();
+    self.current.take().map(|_| Change {
+      tick,
+      stagnation: None,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn census(hash: u64) -> Census {
+    Census { hash, living: 1 }
+  }
+
+  #[test]
+  fn a_cycle_is_announced_once() {
+    let mut watch = Watch::new(MAX_PERIOD);
+    for (tick, hash) in [1, 2, 3].iter().enumerate() {
+      assert!(watch.observe(tick as u64 + 1, census(*hash)).is_none());
+    }
+    let change = watch.observe(4, census(2)).unwrap();
+    assert_eq!(
+      rocket::serde::json::to_string(&change).unwrap(),
+      r#"{"tick":4,"stagnation":"cycling every 2 ticks"}"#
+    );
+    assert!(watch.observe(5, census(3)).is_none());
+    assert!(watch.observe(6, census(2)).is_none());
+
+    let change = watch.observe(7, census(9)).unwrap();
+    assert_eq!(change.tick, 7);
+    assert_eq!(change.stagnation, None);
+  }
+
+  #[test]
+  fn stagnations_end_when_cleared() {
+    let mut watch = Watch::new(MAX_PERIOD);
+    watch.observe(1, census(5));
+    let change = watch.observe(2, census(5)).unwrap();
+    assert_eq!(change.stagnation.as_deref(), Some("static"));
+    let change = watch.clear(3).unwrap();
+    assert_eq!((change.tick, change.stagnation), (3, None));
+    assert!(watch.clear(4).is_none());
+    assert!(watch.observe(5, census(0)).is_none());
+    let extinct = Census { hash: 0, living: 0 };
+    assert_eq!(
+      watch.observe(6, extinct).unwrap().stagnation.as_deref(),
+      Some("extinct")
+    );
+  }
+}
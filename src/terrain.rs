@@ -0,0 +1,200 @@
+use crate::game::Terrain;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Reference colours used to classify pixels of a terrain map image.
+const PLAIN_RGB: (u8, u8, u8) = (53, 48, 40);
+const MEADOW_RGB: (u8, u8, u8) = (60, 110, 40);
+const ROCK_RGB: (u8, u8, u8) = (128, 128, 128);
+const WATER_RGB: (u8, u8, u8) = (40, 80, 160);
+
+#[derive(Clone)]
+pub enum TerrainSource {
+  Flat,
+  Noise,
+  Image(String),
+}
+
+pub fn parse_source(value: &str) -> TerrainSource {
+  match value {
+    "flat" => TerrainSource::Flat,
+    "noise" => TerrainSource::Noise,
+    path => TerrainSource::Image(path.to_string()),
+  }
+}
+
+pub fn generate(
+  source: &TerrainSource,
+  world_width: u32,
+  seed: u64,
+) -> Result<Vec<Terrain>, String> {
+  match source {
+    TerrainSource::Flat => Ok(vec![Terrain::Plain; (world_width * world_width) as usize]),
+    TerrainSource::Noise => Ok(from_noise(world_width, seed)),
+    TerrainSource::Image(path) => from_image(path, world_width),
+  }
+}
+
+/// Lattice of random values, sampled with smoothed bilinear interpolation.
+struct ValueNoise {
+  lattice: Vec<f32>,
+  cells: usize,
+  scale: f32,
+}
+
+impl ValueNoise {
+  fn new(rng: &mut StdRng, world_width: u32, scale: f32) -> ValueNoise {
+    let cells = (world_width as f32 / scale).ceil() as usize + 2;
+    let lattice = (0..cells * cells).map(|_| rng.gen::<f32>()).collect();
+    ValueNoise {
+      lattice,
+      cells,
+      scale,
+    }
+  }
+
+  fn sample(&self, x: u32, y: u32) -> f32 {
+    let fx = x as f32 / self.scale;
+    let fy = y as f32 / self.scale;
+    let (x0, y0) = (fx.floor() as usize, fy.floor() as usize);
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (tx, ty) = (smooth(fx.fract()), smooth(fy.fract()));
+    let at = |i: usize, j: usize| self.lattice[j * self.cells + i];
+
+    let top = at(x0, y0) * (1.0 - tx) + at(x0 + 1, y0) * tx;
+    let bottom = at(x0, y0 + 1) * (1.0 - tx) + at(x0 + 1, y0 + 1) * tx;
+    top * (1.0 - ty) + bottom * ty
+  }
+}
+
+/// Sum a few octaves of value noise, normalised back into [0, 1].
+fn fbm(octaves: &[ValueNoise], x: u32, y: u32) -> f32 {
+  let mut total = 0.0;
+  let mut weight = 1.0;
+  let mut weights = 0.0;
+  for octave in octaves {
+    total += octave.sample(x, y) * weight;
+    weights += weight;
+    weight *= 0.5;
+  }
+  total / weights
+}
+
+fn from_noise(world_width: u32, seed: u64) -> Vec<Terrain> {
+  let mut rng = StdRng::seed_from_u64(seed);
+  let base_scale = (world_width as f32 / 3.0).max(8.0);
+  let rivers: Vec<ValueNoise> = (0..4)
+    .map(|o| ValueNoise::new(&mut rng, world_width, base_scale / (1 << o) as f32))
+    .collect();
+  let soil: Vec<ValueNoise> = (0..4)
+    .map(|o| ValueNoise::new(&mut rng, world_width, base_scale / (1 << o) as f32))
+    .collect();
+
+  let mut terrain = Vec::with_capacity((world_width * world_width) as usize);
+  for y in 0..world_width {
+    for x in 0..world_width {
+      // Rivers follow the contour where the noise crosses its midpoint, which
+      // gives long winding bands rather than round lakes.
+      let ridge = 1.0 - (fbm(&rivers, x, y) * 2.0 - 1.0).abs();
+      let fertility = fbm(&soil, x, y);
+
+      terrain.push(if ridge > 0.95 {
+        Terrain::Water
+      } else if fertility > 0.62 {
+        Terrain::Meadow
+      } else if fertility < 0.3 {
+        Terrain::Rock
+      } else {
+        Terrain::Plain
+      });
+    }
+  }
+
+  terrain
+}
+
+fn classify(r: u8, g: u8, b: u8) -> Terrain {
+  let distance = |(cr, cg, cb): (u8, u8, u8)| {
+    let dr = r as i32 - cr as i32;
+    let dg = g as i32 - cg as i32;
+    let db = b as i32 - cb as i32;
+    dr * dr + dg * dg + db * db
+  };
+
+  vec![
+    (Terrain::Plain, PLAIN_RGB),
+    (Terrain::Meadow, MEADOW_RGB),
+    (Terrain::Rock, ROCK_RGB),
+    (Terrain::Water, WATER_RGB),
+  ]
+  .into_iter()
+  .min_by_key(|(_, rgb)| distance(*rgb))
+  .map(|(terrain, _)| terrain)
+  .unwrap()
+}
+
+/// Load a PPM (P3 or P6) map, stretched to fit the world with nearest-neighbour sampling.
+fn from_image(path: &str, world_width: u32) -> Result<Vec<Terrain>, String> {
+  let bytes = std::fs::read(path).map_err(|e| format!("Could not read terrain {}: {}", path, e))?;
+
+  // The header is four whitespace separated tokens, with optional `#` comments.
+  let mut header: Vec<String> = Vec::new();
+  let mut pos = 0;
+  while header.len() < 4 && pos < bytes.len() {
+    if bytes[pos] == b'#' {
+      while pos < bytes.len() && bytes[pos] != b'\n' {
+        pos += 1;
+      }
+    } else if bytes[pos].is_ascii_whitespace() {
+      pos += 1;
+    } else {
+      let start = pos;
+      while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+        pos += 1;
+      }
+      header.push(String::from_utf8_lossy(&bytes[start..pos]).to_string());
+    }
+  }
+  if header.len() < 4 {
+    return Err(format!("Truncated PPM header in {}", path));
+  }
+  let parse = |s: &String| {
+    s.parse::<u32>()
+      .map_err(|_| format!("Invalid PPM header value {} in {}", s, path))
+  };
+  let (width, height, max_value) = (parse(&header[1])?, parse(&header[2])?, parse(&header[3])?);
+  if width == 0 || height == 0 || max_value == 0 || max_value > 255 {
+    return Err(format!("Unsupported PPM dimensions or depth in {}", path));
+  }
+
+  let pixels: Vec<u8> = match header[0].as_str() {
+    // A single whitespace byte separates the header from binary data.
+    "P6" => bytes[(pos + 1).min(bytes.len())..].to_vec(),
+    "P3" => String::from_utf8_lossy(&bytes[pos..])
+      .split_whitespace()
+      .map(|v| v.parse::<u32>().unwrap_or(0) as u8)
+      .collect(),
+    magic => return Err(format!("Unsupported image format {} in {}", magic, path)),
+  };
+  if (pixels.len() as u64) < width as u64 * height as u64 * 3 {
+    return Err(format!("Truncated PPM data in {}", path));
+  }
+
+  let scale = |v: u8| ((v as u32 * 255) / max_value) as u8;
+  let mut terrain = Vec::with_capacity((world_width * world_width) as usize);
+  for y in 0..world_width {
+    for x in 0..world_width {
+      // Widened so large images and worlds cannot overflow.
+      let px = (x as u64 * width as u64) / world_width as u64;
+      let py = (y as u64 * height as u64) / world_width as u64;
+      let i = ((py * width as u64 + px) * 3) as usize;
+      terrain.push(classify(
+        scale(pixels[i]),
+        scale(pixels[i + 1]),
+        scale(pixels[i + 2]),
+      ));
+    }
+  }
+
+  Ok(terrain)
+}
@@ -0,0 +1,105 @@
+//! A slippy-map pyramid of the garden for `/tiles/<z>/<x>/<y>.png`, so
+//! gardens too large for one picture can be browsed in a map viewer.
+//!
+//! Zoom 0 fits the whole world in one tile. Every zoom level halves the
+//! cells a pixel covers until, at `native_zoom`, each pixel is one cell;
+//! past it cells are drawn larger, up to `OVERZOOM` levels further. Zoomed
+//! out pixels show the majority concept of their block of cells.
+
+use crate::game::Concept;
+use crate::game::Frame;
+use crate::graphics;
+use crate::picture::Picture;
+
+/// Pixels a side of every tile.
+pub const TILE_SIZE: u32 = 256;
+/// Levels past `native_zoom` served.
+pub const OVERZOOM: u32 = 3;
+/// Colour of tile pixels beyond the edge of the world.
+const OUTSIDE: [u8; 3] = [0, 0, 0];
+
+/// The zoom at which a pixel is a cell: the first at which `TILE_SIZE` times
+/// the tiles a side spans the world.
+pub fn native_zoom(world_width: u32) -> u32 {
+  let mut zoom = 0;
+  while TILE_SIZE << zoom < world_width {
+    zoom += 1;
+  }
+  zoom
+}
+
+/// Cells a side of the blocks a pixel covers at `zoom`, 1 from
+/// `native_zoom` on.
+pub fn block_size(world_width: u32, zoom: u32) -> u32 {
+  1 << native_zoom(world_width).saturating_sub(zoom)
+}
+
+/// The world downsampled to the majority concept of each `block` by
+/// `block` cells.
+pub struct Level {
+  /// Blocks a side.
+  width: u32,
+  /// Row by row.
+  concepts: Vec<u8>,
+}
+
+impl Level {
+  /// Ties go to the lowest concept. Blocks at the edge of the world count
+  /// only the cells within it.
+  pub fn downsample(frame: &Frame, world_width: u32, block: u32) -> Level {
+    let width = world_width.div_ceil(block);
+    let cells: Vec<u8> = frame.cells().map(Into::<u8>::into).collect();
+    let mut concepts = Vec::with_capacity((width * width) as usize);
+    // Counts of each concept per block of the current row of blocks.
+    let mut counts = vec![[0u32; Concept::ALL.len()]; width as usize];
+    for rows in cells.chunks(world_width as usize * block as usize) {
+      counts
+        .iter_mut()
+        .for_each(|count| *count = [0; Concept::ALL.len()]);
+      for row in rows.chunks_exact(world_width as usize) {
+        for (x, concept) in row.iter().enumerate() {
+          counts[x / block as usize][*concept as usize] += 1;
+        }
+      }
+      concepts.extend(counts.iter().map(|count| {
+        let (concept, _) = count
+          .iter()
+          .enumerate()
+          .rev()
+          .max_by_key(|(_, n)| **n)
+          .expect("there are concepts");
+        concept as u8
+      }));
+    }
+    Level { width, concepts }
+  }
+
+  /// Tile (`x`, `y`) of `zoom`, from a level of that zoom's block size.
+  pub fn tile(&self, world_width: u32, zoom: u32, x: u32, y: u32) -> Picture {
+    // Pixels a side per block, more than one past the native zoom.
+    let scale = 1 << zoom.saturating_sub(native_zoom(world_width));
+    let mut picture = Picture::new(TILE_SIZE, TILE_SIZE, OUTSIDE);
+    let blocks = TILE_SIZE / scale;
+    for row in 0..blocks {
+      let block_y = y * blocks + row;
+      if block_y >= self.width {
+        break;
+      }
+      for column in 0..blocks {
+        let block_x = x * blocks + column;
+        if block_x >= self.width {
+          break;
+        }
+        let concept = self.concepts[(block_x + block_y * self.width) as usize];
+        let color = graphics::concept_color(Concept::ALL[concept as usize]);
+        picture.fill(
+          column * scale,
+          row * scale,
+          scale,
+          [color.r, color.g, color.b],
+        );
+      }
+    }
+    picture
+  }
+}
@@ -0,0 +1,107 @@
+//! Watchpoints: cells pinned from the window or over HTTP whose state is
+//! recorded after every tick, along with the rule branches that fired, to
+//! tell how a cell got where it is.
+
+use crate::game::CellState;
+use crate::rules::TRACED_BRANCHES;
+use rocket::serde::Serialize;
+use std::collections::VecDeque;
+
+/// Cells that can be pinned at once.
+pub const MAX_WATCHPOINTS: usize = 16;
+/// Words the rule pass writes per watchpoint, see `rules.comp`.
+pub const TRACE_WORDS: usize = 5;
+/// Ticks of history kept per watchpoint.
+const HISTORY: usize = 512;
+
+/// A watched cell after one tick.
+#[derive(Clone, Copy)]
+pub struct Step {
+  /// Ticks run before this step.
+  pub tick: u64,
+  pub cell: CellState,
+  /// Bit `n` is set when rule branch `n` fired, see `RuleSet::branches`.
+  pub fired: u32,
+  /// Whether a mutation replaced the cell instead of its rules running.
+  pub mutated: bool,
+}
+
+impl Step {
+  /// Labels of the branches that fired, in the order they ran.
+  pub fn branches<'a>(&self, labels: &'a [String]) -> Vec<&'a str> {
+    labels
+      .iter()
+      .take(TRACED_BRANCHES)
+      .enumerate()
+      .filter(|(bit, _)| self.fired & (1 << bit) != 0)
+      .map(|(_, label)| label.as_str())
+      .collect()
+  }
+}
+
+pub struct Watchpoint {
+  pub x: u32,
+  pub y: u32,
+  /// Oldest first.
+  pub history: VecDeque<Step>,
+}
+
+impl Watchpoint {
+  pub fn new(x: u32, y: u32) -> Watchpoint {
+    Watchpoint {
+      x,
+      y,
+      history: VecDeque::with_capacity(HISTORY),
+    }
+  }
+
+  pub fn record(&mut self, step: Step) {
+    if self.history.len() == HISTORY {
+      self.history.pop_front();
+    }
+    self.history.push_back(step);
+  }
+
+  pub fn trace(&self, labels: &[String]) -> Trace {
+    Trace {
+      x: self.x,
+      y: self.y,
+      steps: self
+        .history
+        .iter()
+        .map(|step| TracedStep {
+          tick: step.tick,
+          concept: step.cell.concept.name(),
+          blood: step.cell.blood,
+          joy: step.cell.joy,
+          mutated: step.mutated,
+          branches: step
+            .branches(labels)
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+        })
+        .collect(),
+    }
+  }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TracedStep {
+  tick: u64,
+  concept: &'static str,
+  blood: i32,
+  joy: i32,
+  mutated: bool,
+  branches: Vec<String>,
+}
+
+/// A watchpoint's history, oldest first, as served over HTTP.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Trace {
+  x: u32,
+  y: u32,
+  steps: Vec<TracedStep>,
+}
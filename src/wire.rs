@@ -0,0 +1,530 @@
+//! The framed binary format of `/garden/frame`: a header describing the
+//! world and how its cells are laid out, then the cells. Every value is
+//! little-endian.
+//!
+//! ```text
+//! magic        4 bytes   "BGDN"
+//! version      u16       1
+//! header_size  u16       bytes from the magic to the payload
+//! width        u32
+//! height       u32
+//! tick         u64       ticks run before the frame
+//! content      u8        0: whole cells, 1: concepts only
+//! packing      u8        0: none, 1: run-length encoded
+//! stride       u16       bytes per cell in the payload
+//! field_count  u8
+//! fields       per field: name length u8, name, type u8, offset u16
+//! ```
+//!
+//! Field types are 0 for `u8`, 1 for `i16` and 2 for `i32`. Unpacked
+//! payloads are `width * height` cells of `stride` bytes, row by row.
+//! Run-length encoded payloads are runs of a `u32` count followed by the
+//! repeated cell's `stride` bytes.
+
+use crate::game::CellLayout;
+use crate::game::CellState;
+use crate::game::Concept;
+use crate::game::Frame;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use std::io::Write;
+
+const MAGIC: &[u8; 4] = b"BGDN";
+pub const VERSION: u16 = 1;
+
+/// What each cell of a frame carries.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Content {
+  /// Concept, blood and joy, in the world's `CellLayout`.
+  Cells,
+  /// Only the concept, one byte per cell.
+  Concepts,
+}
+
+impl Content {
+  pub fn parse(name: &str) -> Option<Content> {
+    match name {
+      "cells" => Some(Content::Cells),
+      "concepts" => Some(Content::Concepts),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Packing {
+  None,
+  /// Runs of identical cells.
+  RunLength,
+}
+
+impl Packing {
+  pub fn parse(name: &str) -> Option<Packing> {
+    match name {
+      "none" => Some(Packing::None),
+      "rle" => Some(Packing::RunLength),
+      _ => None,
+    }
+  }
+}
+
+enum FieldType {
+  U8 = 0,
+  I16 = 1,
+  I32 = 2,
+}
+
+impl FieldType {
+  fn parse(code: u8) -> Option<FieldType> {
+    match code {
+      0 => Some(FieldType::U8),
+      1 => Some(FieldType::I16),
+      2 => Some(FieldType::I32),
+      _ => None,
+    }
+  }
+
+  fn size(&self) -> usize {
+    match self {
+      FieldType::U8 => 1,
+      FieldType::I16 => 2,
+      FieldType::I32 => 4,
+    }
+  }
+
+  /// The field at the start of `bytes`.
+  fn read(&self, bytes: &[u8]) -> i32 {
+    match self {
+      FieldType::U8 => bytes[0] as i32,
+      FieldType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as i32,
+      FieldType::I32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+    }
+  }
+}
+
+/// Name, type and offset within a cell of each field.
+fn fields(content: Content, layout: CellLayout) -> Vec<(&'static str, FieldType, u16)> {
+  match (content, layout) {
+    (Content::Concepts, _) => vec![("concept", FieldType::U8, 0)],
+    (Content::Cells, CellLayout::Wide) => vec![
+      ("concept", FieldType::I32, 0),
+      ("blood", FieldType::I32, 4),
+      ("joy", FieldType::I32, 8),
+    ],
+    (Content::Cells, CellLayout::Packed) => vec![
+      ("concept", FieldType::U8, 0),
+      ("blood", FieldType::I16, 1),
+      ("joy", FieldType::U8, 3),
+    ],
+  }
+}
+
+fn header(frame: &Frame, width: u32, content: Content, packing: Packing, stride: usize) -> Vec<u8> {
+  let mut out = Vec::new();
+  out.extend_from_slice(MAGIC);
+  out.extend_from_slice(&VERSION.to_le_bytes());
+  // The header size, filled in once known.
+  out.extend_from_slice(&[0, 0]);
+  out.extend_from_slice(&width.to_le_bytes());
+  out.extend_from_slice(&width.to_le_bytes());
+  out.extend_from_slice(&frame.tick.to_le_bytes());
+  out.push(content as u8);
+  out.push(packing as u8);
+  out.extend_from_slice(&(stride as u16).to_le_bytes());
+
+  let fields = fields(content, frame.layout);
+  out.push(fields.len() as u8);
+  for (name, field_type, offset) in fields {
+    out.push(name.len() as u8);
+    out.extend_from_slice(name.as_bytes());
+    out.push(field_type as u8);
+    out.extend_from_slice(&offset.to_le_bytes());
+  }
+
+  let size = out.len() as u16;
+  out[6..8].copy_from_slice(&size.to_le_bytes());
+  out
+}
+
+/// `frame` of a world `width` cells wide, header and payload.
+pub fn encode(frame: &Frame, width: u32, content: Content, packing: Packing) -> Vec<u8> {
+  let concepts: Vec<u8>;
+  let (cells, stride) = match content {
+    Content::Cells => (frame.as_bytes(), frame.layout.cell_size()),
+    Content::Concepts => {
+      concepts = frame.cells().map(Into::<u8>::into).collect();
+      (&concepts[..], 1)
+    }
+  };
+
+  let mut out = header(frame, width, content, packing, stride);
+  match packing {
+    Packing::None => out.extend_from_slice(cells),
+    Packing::RunLength => {
+      let mut runs = cells.chunks_exact(stride).peekable();
+      while let Some(cell) = runs.next() {
+        let mut count: u32 = 1;
+        while runs.next_if_eq(&cell).is_some() {
+          count += 1;
+        }
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(cell);
+      }
+    }
+  }
+  out
+}
+
+/// Reads the little-endian values of a header in order.
+struct Reader<'a> {
+  bytes: &'a [u8],
+  at: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+    let taken = self
+      .bytes
+      .get(self.at..self.at + count)
+      .ok_or_else(|| format!("Frame cut short at byte {}", self.at))?;
+    self.at += count;
+    Ok(taken)
+  }
+
+  fn u8(&mut self) -> Result<u8, String> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u16(&mut self) -> Result<u16, String> {
+    let bytes = self.take(2)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+  }
+
+  fn u32(&mut self) -> Result<u32, String> {
+    let bytes = self.take(4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+  }
+}
+
+/// The cells of a frame `encode` wrote with whole cells, in any layout or
+/// packing, of a world `world_width` cells wide. Fields other than concept,
+/// blood and joy are ignored, and those missing are 0. Cells are only
+/// allocated once the header matches the world and the payload holds them.
+pub fn decode(bytes: &[u8], world_width: u32) -> Result<Vec<CellState>, String> {
+  let mut reader = Reader { bytes, at: 0 };
+  if reader.take(4)? != MAGIC {
+    return Err("Not a bit-garden frame".to_string());
+  }
+  let version = reader.u16()?;
+  if version != VERSION {
+    return Err(format!("Unsupported frame version {}", version));
+  }
+  let header_size = reader.u16()? as usize;
+  let width = reader.u32()?;
+  let height = reader.u32()?;
+  if width != height {
+    return Err(format!("A {}x{} frame is not square", width, height));
+  }
+  if width != world_width {
+    return Err(format!(
+      "Expected a world {} cells wide, resize first",
+      world_width
+    ));
+  }
+  reader.take(8)?;
+  if reader.u8()? != Content::Cells as u8 {
+    return Err("Frames of concepts only lack blood and joy".to_string());
+  }
+  let packing = match reader.u8()? {
+    0 => Packing::None,
+    1 => Packing::RunLength,
+    code => return Err(format!("Unknown packing {}", code)),
+  };
+  let stride = reader.u16()? as usize;
+  if stride == 0 {
+    return Err("Cells must take at least a byte".to_string());
+  }
+
+  let mut fields = Vec::new();
+  for _ in 0..reader.u8()? {
+    let name_len = reader.u8()? as usize;
+    let name = reader.take(name_len)?.to_vec();
+    let code = reader.u8()?;
+    let field_type =
+      FieldType::parse(code).ok_or_else(|| format!("Unknown field type {}", code))?;
+    let offset = reader.u16()? as usize;
+    if offset + field_type.size() > stride {
+      return Err(format!(
+        "Field at {} overruns a {} byte cell",
+        offset, stride
+      ));
+    }
+    fields.push((name, field_type, offset));
+  }
+  let field = |cell: &[u8], wanted: &[u8]| {
+    fields
+      .iter()
+      .find(|(name, _, _)| name == wanted)
+      .map_or(0, |(_, field_type, offset)| {
+        field_type.read(&cell[*offset..])
+      })
+  };
+  let cell = |bytes: &[u8]| -> Result<CellState, String> {
+    let concept = field(bytes, b"concept");
+    Ok(CellState {
+      concept: *Concept::ALL
+        .get(concept as usize)
+        .ok_or_else(|| format!("Unknown concept {}", concept))?,
+      blood: field(bytes, b"blood"),
+      joy: field(bytes, b"joy"),
+    })
+  };
+
+  let payload = bytes
+    .get(header_size..)
+    .ok_or_else(|| "Frame cut short in its header".to_string())?;
+  let count = (width as usize)
+    .checked_mul(width as usize)
+    .ok_or_else(|| format!("A world {} cells wide is too large", width))?;
+  let cells = match packing {
+    Packing::None => {
+      if count.checked_mul(stride) != Some(payload.len()) {
+        return Err(format!(
+          "Expected {} cells of {} bytes, got {} bytes",
+          count,
+          stride,
+          payload.len()
+        ));
+      }
+      payload
+        .chunks_exact(stride)
+        .map(cell)
+        .collect::<Result<Vec<_>, _>>()?
+    }
+    Packing::RunLength => {
+      let mut cells = Vec::new();
+      let mut runs = Reader {
+        bytes: payload,
+        at: 0,
+      };
+      while runs.at < payload.len() {
+        let run = runs.u32()? as usize;
+        let repeated = cell(runs.take(stride)?)?;
+        if run > count - cells.len() {
+          return Err(format!("Runs cover more than {} cells", count));
+        }
+        cells.extend(std::iter::repeat_n(repeated, run));
+      }
+      cells
+    }
+  };
+  if cells.len() != count {
+    return Err(format!("Expected {} cells, got {}", count, cells.len()));
+  }
+  Ok(cells)
+}
+
+/// How a response is compressed, as named in `Content-Encoding`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Compression {
+  Gzip,
+  /// zlib-wrapped deflate, which is what HTTP calls deflate.
+  Deflate,
+}
+
+impl Compression {
+  pub fn name(self) -> &'static str {
+    match self {
+      Compression::Gzip => "gzip",
+      Compression::Deflate => "deflate",
+    }
+  }
+
+  /// The compression to use given an `Accept-Encoding` header: gzip over
+  /// deflate, skipping codings refused with `q=0`.
+  pub fn accepted(accept_encoding: &str) -> Option<Compression> {
+    let accepted: Vec<&str> = accept_encoding
+      .split(',')
+      .filter_map(|coding| {
+        let mut parts = coding.split(';').map(str::trim);
+        let name = parts.next()?;
+        let refused = parts.any(|param| {
+          param
+            .strip_prefix("q=")
+            .and_then(|q| q.parse::<f32>().ok())
+            .is_some_and(|q| q == 0.0)
+        });
+        if refused {
+          None
+        } else {
+          Some(name)
+        }
+      })
+      .collect();
+    [Compression::Gzip, Compression::Deflate]
+      .iter()
+      .copied()
+      .find(|compression| accepted.contains(&compression.name()))
+  }
+
+  pub fn compress(self, bytes: &[u8]) -> Vec<u8> {
+    let level = flate2::Compression::fast();
+    let compressed = match self {
+      Compression::Gzip => {
+        let mut encoder = GzEncoder::new(Vec::new(), level);
+        encoder.write_all(bytes).and_then(|_| encoder.finish())
+      }
+      Compression::Deflate => {
+        let mut encoder = ZlibEncoder::new(Vec::new(), level);
+        encoder.write_all(bytes).and_then(|_| encoder.finish())
+      }
+    };
+    compressed.expect("compressing into memory cannot fail")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A world 4 cells wide, in runs of 3, 1 and 12 identical cells.
+  fn cells() -> Vec<CellState> {
+    let soil = CellState {
+      concept: Concept::Soil,
+      blood: 0,
+      joy: 0,
+    };
+    let rose = CellState {
+      concept: Concept::Rose,
+      blood: -300,
+      joy: 40,
+    };
+    let elder = CellState {
+      concept: Concept::Elder,
+      blood: 7,
+      joy: 255,
+    };
+    let mut cells = vec![rose; 3];
+    cells.push(elder);
+    cells.extend(vec![soil; 12]);
+    cells
+  }
+
+  /// The payload of an encoded frame.
+  fn payload(bytes: &[u8]) -> &[u8] {
+    &bytes[u16::from_le_bytes([bytes[6], bytes[7]]) as usize..]
+  }
+
+  #[test]
+  fn frames_round_trip() {
+    for &layout in [CellLayout::Wide, CellLayout::Packed].iter() {
+      for &packing in [Packing::None, Packing::RunLength].iter() {
+        let frame = Frame::of(9, layout, &cells());
+        let decoded = decode(&encode(&frame, 4, Content::Cells, packing), 4).unwrap();
+        assert!(decoded == cells());
+      }
+    }
+  }
+
+  #[test]
+  fn runs_of_identical_cells_share_a_count() {
+    let frame = Frame::of(0, CellLayout::Packed, &cells());
+    let bytes = encode(&frame, 4, Content::Cells, Packing::RunLength);
+    let runs: Vec<u32> = payload(&bytes)
+      .chunks_exact(8)
+      .map(|run| u32::from_le_bytes([run[0], run[1], run[2], run[3]]))
+      .collect();
+    assert_eq!(runs, vec![3, 1, 12]);
+
+    let bytes = encode(&frame, 4, Content::Concepts, Packing::RunLength);
+    assert_eq!(
+      payload(&bytes),
+      &[3, 0, 0, 0, 2, 1, 0, 0, 0, 4, 12, 0, 0, 0, 0][..]
+    );
+  }
+
+  #[test]
+  fn packed_frames_describe_their_fields() {
+    let frame = Frame::of(9, CellLayout::Packed, &cells());
+    let bytes = encode(&frame, 4, Content::Cells, Packing::None);
+    assert_eq!(&bytes[0..6], b"BGDN\x01\x00");
+    assert_eq!(&bytes[8..16], &[4, 0, 0, 0, 4, 0, 0, 0][..]);
+    assert_eq!(&bytes[16..24], &9u64.to_le_bytes()[..]);
+    // Whole cells, unpacked, 4 bytes each, then three fields.
+    assert_eq!(&bytes[24..29], &[0, 0, 4, 0, 3][..]);
+    let fields = [
+      &[7][..],
+      b"concept",
+      &[0, 0, 0],
+      &[5],
+      b"blood",
+      &[1, 1, 0],
+      &[3],
+      b"joy",
+      &[0, 3, 0],
+    ]
+    .concat();
+    assert_eq!(&bytes[29..], &[&fields[..], payload(&bytes)].concat()[..]);
+    // A rose with -300 blood and 40 joy.
+    assert_eq!(&payload(&bytes)[0..4], &[2, 0xD4, 0xFE, 40][..]);
+  }
+
+  #[test]
+  fn rejects_truncated_headers() {
+    let frame = Frame::of(0, CellLayout::Wide, &cells());
+    let bytes = encode(&frame, 4, Content::Cells, Packing::None);
+    let header_size = bytes.len() - payload(&bytes).len();
+    for cut in [0, 3, 8, 25, header_size - 1].iter() {
+      let error = decode(&bytes[..*cut], 4).err().unwrap();
+      assert!(error.starts_with("Frame cut short"), "{}", error);
+    }
+  }
+
+  #[test]
+  fn rejects_runs_past_the_last_cell() {
+    let frame = Frame::of(0, CellLayout::Packed, &cells());
+    let mut bytes = encode(&frame, 4, Content::Cells, Packing::RunLength);
+    let at = bytes.len() - payload(&bytes).len();
+    bytes[at] = 4;
+    assert_eq!(
+      decode(&bytes, 4).err().unwrap(),
+      "Runs cover more than 16 cells"
+    );
+
+    bytes[at] = 2;
+    assert_eq!(
+      decode(&bytes, 4).err().unwrap(),
+      "Expected 16 cells, got 15"
+    );
+  }
+
+  #[test]
+  fn rejects_frames_of_other_worlds_before_reading_cells() {
+    let frame = Frame::of(0, CellLayout::Packed, &cells());
+    let bytes = encode(&frame, 4, Content::Cells, Packing::None);
+    assert_eq!(
+      decode(&bytes, 8).err().unwrap(),
+      "Expected a world 8 cells wide, resize first"
+    );
+
+    // A bare header claiming a huge world.
+    let mut header = bytes[..bytes.len() - payload(&bytes).len()].to_vec();
+    header[8..16].copy_from_slice(&[0x60, 0xEA, 0, 0, 0x60, 0xEA, 0, 0]);
+    assert!(decode(&header, 4).is_err());
+    assert_eq!(
+      decode(&header, 60000).err().unwrap(),
+      "Expected 3600000000 cells of 4 bytes, got 0 bytes"
+    );
+  }
+
+  #[test]
+  fn rejects_huge_runs() {
+    let frame = Frame::of(0, CellLayout::Packed, &cells());
+    let mut bytes = encode(&frame, 4, Content::Cells, Packing::RunLength);
+    let at = bytes.len() - payload(&bytes).len();
+    bytes[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(
+      decode(&bytes, 4).err().unwrap(),
+      "Runs cover more than 16 cells"
+    );
+  }
+}
@@ -12,8 +12,10 @@ pub struct VkCmdPool {
 
 impl VkCmdPool {
     pub fn new(state: Rc<VulkanState>) -> VkCmdPool {
+        // Lets a command buffer be recorded again once it has executed.
         let command_pool_create_info = vk::CommandPoolCreateInfo::builder()
             .queue_family_index(state.queue_family_index)
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
             .build();
         let command_pool = unsafe {
             state
@@ -108,6 +110,44 @@ impl VkCmdPool {
         };
     }
 
+    pub fn copy_buffer(
+        &self,
+        src: vk::Buffer,
+        dst: vk::Buffer,
+        size: u64,
+        cmd_buffer_index: usize,
+    ) {
+        let region = vk::BufferCopy::builder().size(size).build();
+        unsafe {
+            self.state.device.cmd_copy_buffer(
+                self.cmd_buffers[cmd_buffer_index],
+                src,
+                dst,
+                &[region],
+            )
+        };
+    }
+
+    /// Submit only the given command buffers, in order, rather than the whole pool.
+    pub fn submit_buffers(
+        &self,
+        queue: vk::Queue,
+        cmd_buffer_indices: &[usize],
+        fence: Option<vk::Fence>,
+    ) {
+        let cmd_buffers: Vec<vk::CommandBuffer> = cmd_buffer_indices
+            .iter()
+            .map(|&i| self.cmd_buffers[i])
+            .collect();
+        let submit_info = vk::SubmitInfo::builder().command_buffers(&cmd_buffers);
+        unsafe {
+            self.state
+                .device
+                .queue_submit(queue, &[submit_info.build()], fence.unwrap())
+                .expect("[ERR] Could not submit queue.")
+        };
+    }
+
     pub fn submit(&self, queue: vk::Queue, fence: Option<vk::Fence>) {
         let submit_info = vk::SubmitInfo::builder().command_buffers(&self.cmd_buffers);
         unsafe {
@@ -25,10 +25,16 @@ pub struct VkBuffer {
 
 impl VkBuffer {
     pub fn new(vkstate: Rc<VulkanState>, size: u64) -> Self {
+        VkBuffer::with_usage(vkstate, size, vk::BufferUsageFlags::STORAGE_BUFFER)
+    }
+
+    /// Create a buffer for other uses than a storage buffer, e.g. as the
+    /// source or destination of transfers.
+    pub fn with_usage(vkstate: Rc<VulkanState>, size: u64, usage: vk::BufferUsageFlags) -> Self {
         let queue_indices = &[vkstate.queue_family_index];
         let buffer_create_info = vk::BufferCreateInfo::builder()
             .size(size)
-            .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+            .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
             .queue_family_indices(queue_indices);
 
@@ -107,8 +113,32 @@ impl Drop for VkBuffer {
     }
 }
 
+/// Memory types every buffer in the slice can be bound to, as a bit mask.
+pub fn common_memory_type_bits(buffers: &Vec<&VkBuffer>) -> u32 {
+    buffers.iter().fold(!0, |bits, buffer| {
+        bits & buffer.get_buffer_memory_requirements().memory_type_bits
+    })
+}
+
 impl VkMem {
     pub fn find_mem(vkstate: Rc<VulkanState>, size: u64) -> Option<Self> {
+        VkMem::find_mem_with(
+            vkstate,
+            size,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            !0,
+        )
+    }
+
+    /// Allocate from a memory type having all of `flags`, restricted to the
+    /// types allowed by `type_bits` (see `common_memory_type_bits`).
+    /// `None` if there is no such type or the allocation fails.
+    pub fn find_mem_with(
+        vkstate: Rc<VulkanState>,
+        size: u64,
+        flags: vk::MemoryPropertyFlags,
+        type_bits: u32,
+    ) -> Option<Self> {
         let mem_props = unsafe {
             vkstate
                 .instance
@@ -123,12 +153,8 @@ impl VkMem {
                 i,
                 buffer_max_size as f64 / 1024.0 / 1024.0
             );
-            if mem_type_props
-                .property_flags
-                .contains(vk::MemoryPropertyFlags::HOST_VISIBLE)
-                && mem_type_props
-                    .property_flags
-                    .contains(vk::MemoryPropertyFlags::HOST_COHERENT)
+            if type_bits & (1 << i) != 0
+                && mem_type_props.property_flags.contains(flags)
                 && mem_props.memory_heaps[mem_type_props.heap_index as usize].size > size
             {
                 mem_index = Some(i);
@@ -144,12 +170,7 @@ impl VkMem {
             .allocation_size(size)
             .memory_type_index(mem_index)
             .build();
-        let vulkan_mem = unsafe {
-            vkstate
-                .device
-                .allocate_memory(&allocate_nfo, None)
-                .expect("[ERR] Could not allocate memory in device.")
-        };
+        let vulkan_mem = unsafe { vkstate.device.allocate_memory(&allocate_nfo, None).ok()? };
 
         let mem_struct: VkMem = VkMem {
             size: size,
@@ -20,7 +20,15 @@ pub struct VkShader {
 impl VkShader {
     pub fn new(state: Rc<VulkanState>, path: &PathBuf, entry_point: CString) -> Self {
         let shader_bytecode = to_vec32(load_file(path).expect("[ERR] Could not load shader file."));
+        VkShader::from_bytecode(state, shader_bytecode, entry_point)
+    }
 
+    /// Build a shader from SPIR-V words already in memory, e.g. compiled at runtime.
+    pub fn from_bytecode(
+        state: Rc<VulkanState>,
+        shader_bytecode: Vec<u32>,
+        entry_point: CString,
+    ) -> Self {
         let shader_module_create_info = vk::ShaderModuleCreateInfo::builder()
             .code(&shader_bytecode)
             .build();
@@ -35,14 +35,30 @@ impl Drop for VulkanState {
     }
 }
 
-pub fn print_work_limits(vulkan: Rc<VulkanState>) {
+/// Limits of the physical device, used to size dispatches.
+pub fn device_limits(vulkan: Rc<VulkanState>) -> vk::PhysicalDeviceLimits {
+    let physical_device_props = unsafe {
+        vulkan
+            .instance
+            .get_physical_device_properties(vulkan.physical_device)
+    };
+
+    physical_device_props.limits
+}
+
+/// Whether the device is integrated, discrete, virtual or a CPU.
+pub fn device_type(vulkan: Rc<VulkanState>) -> vk::PhysicalDeviceType {
     let physical_device_props = unsafe {
         vulkan
             .instance
             .get_physical_device_properties(vulkan.physical_device)
     };
 
-    let physical_limits = physical_device_props.limits;
+    physical_device_props.device_type
+}
+
+pub fn print_work_limits(vulkan: Rc<VulkanState>) {
+    let physical_limits = device_limits(vulkan);
     let work_group_count = physical_limits.max_compute_work_group_count;
     let work_group_size = physical_limits.max_compute_work_group_size;
     let work_group_invocation = physical_limits.max_compute_work_group_invocations;